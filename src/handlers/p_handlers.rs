@@ -1,20 +1,28 @@
 use std::borrow::Cow;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use actix::{Addr, Handler, StreamHandler};
-use actix_web::{get, post, web, Either, HttpRequest, HttpResponse, Responder};
+use actix_web::http::header;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
 use actix_web_actors::ws;
 use base64::engine::general_purpose;
 use base64::Engine;
-use futures::TryFutureExt;
+use futures::TryStreamExt;
 use redis::Client;
 
 use crate::models::err_models::VpError;
 use crate::models::p_models::{
-    AppState, CanvasResponse, UpdatePixel, VpConnect, VpDisconnect, VpListener, VpRes, VpSrv,
-    WaitTime,
+    AppState, AppliedResponse, AreaResponse, BatchReadItem, CanvasResponse, ChecksumResponse,
+    ClearResponse, CountResponse, DeltaQuery, DumpQuery, ExpectedQuery, FillResponse, HeatmapQuery,
+    HistogramQuery, HistoryPage, HistoryPageQuery, ImportQuery, ImportResponse, LimitQuery,
+    ModifiedResponse, OwnerQuery, PackedQuery, PixelBatch, PixelLoc, PixelQuery, PngQuery,
+    ReadOnly, RegionQuery, ReshardingResponse, RgbResponse, TimelapseQuery, TopColorsQuery,
+    TotalResponse, UpdatePixel, VpConnect, VpDisconnect, VpListener, VpRes, VpSrv, WaitTime,
 };
-use crate::models::scylla_models::ScyllaManager;
-use crate::services::p_services::diff_last_placed;
+use crate::models::scylla_models::{ReadOp, Rect, RenderOptions, ScyllaManager, MAX_REGION_AREA};
+use crate::services::p_services::sync_place;
+use crate::services::rate_services::RateLimiter;
+use crate::services::sig_services::{erase_message, placement_message, verify_placement};
 
 #[get("/canvas")]
 async fn get_canvas(
@@ -38,6 +46,103 @@ async fn get_canvas(
     }))
 }
 
+// newline delimited json dump of all painted pixels, for backups
+// page_size : rows per scylla page, smaller keeps a slow backup job light
+#[get("/canvas/snapshot")]
+async fn canvas_snapshot(
+    query: web::Query<DumpQuery>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let page_size = query.page_size.map(|p| p.clamp(1, 10000));
+    let rows = scylla
+        .stream_canvas(&app_data.canvas_id, page_size)?
+        .map_ok(|row| {
+            let mut line = serde_json::to_vec(&row).unwrap_or_default();
+            line.push(b'\n');
+            web::Bytes::from(line)
+        });
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(rows))
+}
+
+// dimension, palette and cooldown of the canvas
+#[get("/canvas/info")]
+async fn canvas_info(
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    Ok(HttpResponse::Ok().json(scylla.canvas_info(&app_data.canvas_id)?))
+}
+
+// canvas as a png, for sharing snapshots : )
+// Last-Modified / If-Modified-Since let proxies skip the render
+#[get("/canvas/png")]
+async fn canvas_png(
+    req: HttpRequest,
+    query: web::Query<PngQuery>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let canvas_id = &app_data.canvas_id;
+    let modified = UNIX_EPOCH
+        + Duration::from_secs(u64::try_from(scylla.last_modified(canvas_id).await?).unwrap_or(0));
+    let since = req
+        .headers()
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|since| since.to_str().ok())
+        .and_then(|since| since.parse::<header::HttpDate>().ok());
+    let last_modified = header::LastModified(modified.into());
+    if since.is_some_and(|since| SystemTime::from(since) >= modified) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(last_modified)
+            .finish());
+    }
+    let query = query.into_inner();
+    let options = RenderOptions {
+        scale: query.scale.unwrap_or(1),
+        watermark: query.watermark.filter(|w| !w.is_empty()),
+    };
+    let png = scylla
+        .render_png(canvas_id, scylla.palette(canvas_id)?, &options)
+        .await?;
+    Ok(HttpResponse::Ok()
+        .content_type("image/png")
+        .insert_header(last_modified)
+        .body(png))
+}
+
+// one zoom level of the canvas, 4 byte big endian side then side * side color bytes (x major)
+// level 0 is full res, past the last level the 1x1 one is returned
+#[get("/canvas/mipmap/{level}")]
+async fn canvas_mipmap(
+    path: web::Path<u32>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let canvas_id = &app_data.canvas_id;
+    let level = path.into_inner().saturating_add(1);
+    let mut mips = scylla
+        .render_mipmap(canvas_id, level, scylla.palette(canvas_id)?)
+        .await?;
+    let cells = mips.pop().unwrap_or_default();
+    let side = u32::try_from(cells.len().isqrt()).map_err(VpError::from)?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .body([&side.to_be_bytes()[..], &cells].concat()))
+}
+
+// unix seconds of the last change of the canvas
+#[get("/canvas/modified")]
+async fn canvas_modified(
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let last_modified = scylla.last_modified(&app_data.canvas_id).await?;
+    Ok(HttpResponse::Ok().json(ModifiedResponse { last_modified }))
+}
+
 #[get("/opbnbplace")]
 pub async fn opbnbplace(
     req: HttpRequest,
@@ -50,12 +155,20 @@ pub async fn opbnbplace(
 #[get("/pixel/{x}/{y}")]
 pub async fn pixel_info(
     path: web::Path<(u32, u32)>,
+    query: web::Query<PixelQuery>,
     app_data: web::Data<AppState<'_>>,
     scylla: web::Data<ScyllaManager>,
 ) -> actix_web::Result<impl Responder> {
     let (x, y) = path.into_inner();
     if x < app_data.canvas_dim && y < app_data.canvas_dim {
-        let res = scylla.get_pixel(x, y).await;
+        let res = match query.default {
+            Some(color) => {
+                scylla
+                    .get_pixel_or_default(&app_data.canvas_id, x, y, color)
+                    .await
+            }
+            None => scylla.get_pixel(&app_data.canvas_id, x, y).await,
+        };
         match res {
             Ok(pixel) => Ok(HttpResponse::Ok().json(pixel)),
             Err(VpError::NoPixelData) => Ok(HttpResponse::NotFound().body("no Pixel Data Found")),
@@ -66,58 +179,538 @@ pub async fn pixel_info(
     }
 }
 
+#[get("/healthz")]
+pub async fn healthz(scylla: web::Data<ScyllaManager>) -> impl Responder {
+    match scylla.health_check().await {
+        Ok(()) => HttpResponse::Ok().body("ok"),
+        Err(e) => {
+            log::error!("Health check failed : {}", e);
+            HttpResponse::ServiceUnavailable().body(e.to_string())
+        }
+    }
+}
+
+#[get("/heatmap")]
+pub async fn heatmap(
+    query: web::Query<HeatmapQuery>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let res = scylla
+        .placement_heatmap(&app_data.canvas_id, query.bucket.unwrap_or(10), query.since)
+        .await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+// most placed colors of the window, a day by default : color of the day
+#[get("/stats/colors/top")]
+pub async fn top_colors(
+    query: web::Query<TopColorsQuery>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let window = query.window.unwrap_or(86400).max(1);
+    let limit = query.limit.unwrap_or(5).clamp(1, 100);
+    let res = scylla
+        .top_colors(&app_data.canvas_id, window, limit)
+        .await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+// painted cells per color of the canvas, or of the region in the query
+#[get("/stats/colors")]
+pub async fn color_histogram(
+    query: web::Query<HistogramQuery>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let region = match (query.x0, query.y0, query.x1, query.y1) {
+        (Some(x0), Some(y0), Some(x1), Some(y1)) => {
+            if ![x0, y0, x1, y1].iter().all(|c| *c < app_data.canvas_dim) {
+                Err(VpError::CanvasSizeMismatch)?
+            }
+            Some((x0, y0, x1, y1))
+        }
+        (None, None, None, None) => None,
+        _ => return Ok(HttpResponse::BadRequest().body("region needs x0, y0, x1 and y1")),
+    };
+    let res = scylla.color_histogram(&app_data.canvas_id, region).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[get("/leaderboard")]
+pub async fn leaderboard(
+    query: web::Query<LimitQuery>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let limit = query.limit.unwrap_or(10).clamp(1, 100);
+    let res = scylla.top_players(limit).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+// latest placements of the whole site, for the live ticker
+#[get("/recent")]
+pub async fn recent_placements(
+    query: web::Query<LimitQuery>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let res = scylla.recent_placements(limit).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+// placements since a timestamp, for clients reconnecting to the websocket
+// 410 when the gap is too old or too big, then the canvas has to be fetched again
+#[get("/delta")]
+pub async fn delta_since(
+    query: web::Query<DeltaQuery>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let res = scylla.delta_since(&app_data.canvas_id, query.since).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+// biggest connected area of one color with its bounding box, scans the whole canvas
+#[get("/stats/color/{color}/largest")]
+pub async fn largest_region(
+    path: web::Path<i32>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let (size, bounds) = scylla
+        .largest_region(&app_data.canvas_id, path.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(AreaResponse { size, bounds }))
+}
+
+// painted share of the canvas in percent, for the landing page progress bar
+#[get("/stats/fill")]
+pub async fn fill_percentage(
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let percentage = scylla.fill_percentage(&app_data.canvas_id).await?;
+    Ok(HttpResponse::Ok().json(FillResponse { percentage }))
+}
+
+// current standings of the canvas war, team -> placements
+#[get("/stats/teams")]
+pub async fn team_scores(scylla: web::Data<ScyllaManager>) -> actix_web::Result<impl Responder> {
+    Ok(HttpResponse::Ok().json(scylla.team_scores().await?))
+}
+
+#[get("/stats/total")]
+pub async fn total_placements(
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let total = scylla.total_placements().await?;
+    Ok(HttpResponse::Ok().json(TotalResponse { total }))
+}
+
+// color of the pixel as rgb, unpainted pixels are the background
+#[get("/pixel/{x}/{y}/rgb")]
+pub async fn pixel_rgb(
+    path: web::Path<(u32, u32)>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let (x, y) = path.into_inner();
+    let palette = scylla.palette(&app_data.canvas_id)?;
+    let rgb = scylla
+        .get_pixel_rgb(&app_data.canvas_id, x, y, palette)
+        .await?;
+    Ok(HttpResponse::Ok().json(RgbResponse { rgb }))
+}
+
+#[get("/pixel/{x}/{y}/neighborhood")]
+pub async fn pixel_neighborhood(
+    path: web::Path<(u32, u32)>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let (x, y) = path.into_inner();
+    let res = scylla
+        .get_pixel_neighborhood(&app_data.canvas_id, x, y)
+        .await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[get("/pixel/{x}/{y}/history")]
+pub async fn pixel_history(
+    path: web::Path<(u32, u32)>,
+    query: web::Query<LimitQuery>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let (x, y) = path.into_inner();
+    // default 10 edits, max 100 : )
+    let limit = query.limit.unwrap_or(10).clamp(1, 100);
+    if x < app_data.canvas_dim && y < app_data.canvas_dim {
+        let res = scylla
+            .get_pixel_history(&app_data.canvas_id, x, y, limit)
+            .await?;
+        Ok(HttpResponse::Ok().json(res))
+    } else {
+        Err(VpError::CanvasSizeMismatch)?
+    }
+}
+
+// history of a pixel in pages, follow nextCursor with ?before= : )
+#[get("/pixel/{x}/{y}/history/page")]
+pub async fn pixel_history_page(
+    path: web::Path<(u32, u32)>,
+    query: web::Query<HistoryPageQuery>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let (x, y) = path.into_inner();
+    let limit = query.limit.unwrap_or(10).clamp(1, 100);
+    let (items, next_cursor) = scylla
+        .get_pixel_history_page(&app_data.canvas_id, x, y, query.before, limit)
+        .await?;
+    Ok(HttpResponse::Ok().json(HistoryPage { items, next_cursor }))
+}
+
+// who owned the pixel at ?ts
+#[get("/pixel/{x}/{y}/owner")]
+pub async fn pixel_owner(
+    path: web::Path<(u32, u32)>,
+    query: web::Query<OwnerQuery>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let (x, y) = path.into_inner();
+    match scylla.owner_at(&app_data.canvas_id, x, y, query.ts).await {
+        Ok(pixel) => Ok(HttpResponse::Ok().json(pixel)),
+        Err(VpError::NoPixelData) => Ok(HttpResponse::NotFound().body("no Pixel Data Found")),
+        Err(e) => Err(e)?,
+    }
+}
+
+#[post("/pixels")]
+pub async fn pixels_info(
+    coords: web::Json<Vec<(u32, u32)>>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let coords = coords.into_inner();
+    if coords.len() as u64 > MAX_REGION_AREA {
+        Err(VpError::RegionTooLarge)?
+    }
+    if coords
+        .iter()
+        .all(|(x, y)| *x < app_data.canvas_dim && *y < app_data.canvas_dim)
+    {
+        let res = scylla.get_pixels(&app_data.canvas_id, &coords).await?;
+        Ok(HttpResponse::Ok().json(res))
+    } else {
+        Err(VpError::CanvasSizeMismatch)?
+    }
+}
+
+// painted cells of the region, for zone scoring
+#[get("/region/{x0}/{y0}/{x1}/{y1}/count")]
+pub async fn region_count(
+    path: web::Path<(u32, u32, u32, u32)>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let (x0, y0, x1, y1) = path.into_inner();
+    if ![x0, y0, x1, y1].iter().all(|c| *c < app_data.canvas_dim) {
+        Err(VpError::CanvasSizeMismatch)?
+    }
+    let painted = scylla
+        .count_painted(&app_data.canvas_id, x0, y0, x1, y1)
+        .await?;
+    Ok(HttpResponse::Ok().json(CountResponse { painted }))
+}
+
+// checksum of the region, compare it between nodes or around a migration
+#[get("/region/{x0}/{y0}/{x1}/{y1}/checksum")]
+pub async fn region_checksum(
+    path: web::Path<(u32, u32, u32, u32)>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let (x0, y0, x1, y1) = path.into_inner();
+    if ![x0, y0, x1, y1].iter().all(|c| *c < app_data.canvas_dim) {
+        Err(VpError::CanvasSizeMismatch)?
+    }
+    let checksum = scylla
+        .region_checksum(&app_data.canvas_id, x0, y0, x1, y1)
+        .await?;
+    Ok(HttpResponse::Ok().json(ChecksumResponse {
+        checksum: format!("{:016x}", checksum),
+    }))
+}
+
+// the region's history as an animated gif, one frame per interval seconds (default an hour)
+#[get("/region/{x0}/{y0}/{x1}/{y1}/timelapse.gif")]
+pub async fn region_timelapse(
+    path: web::Path<(u32, u32, u32, u32)>,
+    query: web::Query<TimelapseQuery>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let (x0, y0, x1, y1) = path.into_inner();
+    if ![x0, y0, x1, y1].iter().all(|c| *c < app_data.canvas_dim) {
+        Err(VpError::CanvasSizeMismatch)?
+    }
+    let canvas_id = &app_data.canvas_id;
+    let gif = scylla
+        .render_timelapse_gif(
+            canvas_id,
+            Rect::new(x0, y0, x1, y1),
+            query.interval.unwrap_or(3600),
+            scylla.palette(canvas_id)?,
+        )
+        .await?;
+    Ok(HttpResponse::Ok().content_type("image/gif").body(gif))
+}
+
+// region in the packed binary format, for initial loads
+#[get("/region/{x0}/{y0}/{x1}/{y1}/packed")]
+pub async fn region_packed(
+    path: web::Path<(u32, u32, u32, u32)>,
+    query: web::Query<PackedQuery>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let (x0, y0, x1, y1) = path.into_inner();
+    if ![x0, y0, x1, y1].iter().all(|c| *c < app_data.canvas_dim) {
+        Err(VpError::CanvasSizeMismatch)?
+    }
+    let canvas_id = &app_data.canvas_id;
+    let packed = if query.raw.unwrap_or(false) {
+        scylla.get_region_packed(canvas_id, x0, y0, x1, y1).await?
+    } else {
+        scylla.get_region_rle(canvas_id, x0, y0, x1, y1).await?
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .body(packed))
+}
+
+#[get("/region/{x0}/{y0}/{x1}/{y1}")]
+pub async fn region_info(
+    path: web::Path<(u32, u32, u32, u32)>,
+    query: web::Query<RegionQuery>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let (x0, y0, x1, y1) = path.into_inner();
+    if [x0, y0, x1, y1].iter().all(|c| *c < app_data.canvas_dim) {
+        let res = match query.since {
+            Some(since) => {
+                scylla
+                    .region_since(&app_data.canvas_id, x0, y0, x1, y1, since)
+                    .await?
+            }
+            None if query.empty == Some(true) => {
+                scylla
+                    .get_region_filled(&app_data.canvas_id, x0, y0, x1, y1)
+                    .await?
+            }
+            None => {
+                scylla
+                    .get_region(&app_data.canvas_id, x0, y0, x1, y1)
+                    .await?
+            }
+        };
+        Ok(HttpResponse::Ok().json(res))
+    } else {
+        Err(VpError::CanvasSizeMismatch)?
+    }
+}
+
+// player row, nonce is the one to sign the next placement with
+#[get("/user/{address}")]
+pub async fn user_info(
+    path: web::Path<String>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    match scylla.get_user(&path.into_inner()).await {
+        Ok(user) => Ok(HttpResponse::Ok().json(user)),
+        Err(VpError::InvalidUser) => Ok(HttpResponse::NotFound().body("no User Found")),
+        Err(e) => Err(e)?,
+    }
+}
+
+// the user's last placed pixel, typed. 204 after an erase
+#[get("/user/{address}/pixel")]
+pub async fn user_pixel(
+    path: web::Path<String>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    match scylla.get_user(&path.into_inner()).await {
+        Ok(user) => match user.pixel()? {
+            Some(pixel) => Ok(HttpResponse::Ok().json(pixel)),
+            // last action was an erase
+            None => Ok(HttpResponse::NoContent().finish()),
+        },
+        Err(VpError::InvalidUser) => Ok(HttpResponse::NotFound().body("no User Found")),
+        Err(e) => Err(e)?,
+    }
+}
+
+#[get("/user/{address}/cooldown")]
+pub async fn user_cooldown(
+    path: web::Path<String>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let address = path.into_inner();
+    let rem_wait = scylla.get_cooldown_remaining(&address).await?;
+    Ok(HttpResponse::Ok().json(WaitTime { rem_wait }))
+}
+
+#[post("/users")]
+pub async fn users_info(
+    addresses: web::Json<Vec<String>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let res = scylla.get_users(&addresses).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+// several reads in one request, eg: [{"op":"user","address":".."},{"op":"fill_percentage"}]
+#[post("/batch")]
+pub async fn batch_read(
+    ops: web::Json<Vec<ReadOp>>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let res: Vec<_> = scylla
+        .batch_read(&app_data.canvas_id, ops.into_inner())
+        .await?
+        .into_iter()
+        .map(|res| match res {
+            Ok(value) => BatchReadItem::Ok(value),
+            Err(e) => BatchReadItem::Error {
+                name: e.name(),
+                message: e.to_string(),
+            },
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(res))
+}
+
+// pixels of the region the address still owns
+#[get("/user/{address}/region/{x0}/{y0}/{x1}/{y1}")]
+pub async fn user_region(
+    path: web::Path<(String, u32, u32, u32, u32)>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let (address, x0, y0, x1, y1) = path.into_inner();
+    if ![x0, y0, x1, y1].iter().all(|c| *c < app_data.canvas_dim) {
+        Err(VpError::CanvasSizeMismatch)?
+    }
+    let res = scylla
+        .user_pixels_in_region(&app_data.canvas_id, &address, x0, y0, x1, y1)
+        .await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[get("/user/{address}/recent")]
+pub async fn user_recent(
+    path: web::Path<String>,
+    query: web::Query<LimitQuery>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let address = path.into_inner();
+    let limit = query.limit.unwrap_or(10).clamp(1, 100);
+    let res = scylla.get_user_recent(&address, limit).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+// the user's whole placement history, for audits
+#[get("/user/{address}/history.csv")]
+pub async fn user_history_csv(
+    path: web::Path<String>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    let csv = scylla.export_user_history_csv(&path.into_inner()).await?;
+    Ok(HttpResponse::Ok().content_type("text/csv").body(csv))
+}
+
+// can the user place here ? nothing is written, the signature is not needed
+#[post("/pixel/validate")]
+async fn validate_pixel(
+    update_req: web::Json<UpdatePixel>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    scylla
+        .validate_placement(&app_data.canvas_id, &update_req)
+        .await?;
+    Ok(HttpResponse::Ok())
+}
+
+// support tooling : every gate of the placement with its verdict, nothing is written
+#[post("/pixel/explain")]
+async fn explain_pixel(
+    http_req: HttpRequest,
+    update_req: web::Json<UpdatePixel>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+    limiter: web::Data<dyn RateLimiter>,
+) -> actix_web::Result<impl Responder> {
+    let mut decision = scylla
+        .explain_placement(&app_data.canvas_id, &update_req)
+        .await?;
+    decision.push("rate_limit", limiter.peek(&client_key(&http_req), 1).await);
+    decision.push("signature", verify_signed(&update_req));
+    Ok(HttpResponse::Ok().json(decision))
+}
+
+// rate limiter key of a request : the peer ip
+// behind a proxy every client shares the bucket of the proxy : )
+fn client_key(req: &HttpRequest) -> String {
+    req.peer_addr()
+        .map_or_else(String::new, |addr| addr.ip().to_string())
+}
+
 #[post("/pixel/update")]
 async fn update_pixel(
+    http_req: HttpRequest,
     update_req: web::Json<UpdatePixel>,
     app_data: web::Data<AppState<'_>>,
     redis: web::Data<Client>,
     scylla: web::Data<ScyllaManager>,
-    pu_srv: web::Data<Addr<VpSrv<'_>>>,
+    limiter: web::Data<dyn RateLimiter>,
 ) -> actix_web::Result<impl Responder> {
+    limiter.acquire(&client_key(&http_req), 1).await?;
     let req = update_req.into_inner();
     let mut conn = redis
         .get_tokio_connection_manager()
         .await
         .map_err(VpError::RedisErr)?;
-    // color size-> 16 colors [0,15], max val -> 15
-    if req.color <= 15 {
+    // a palette index, at most MAX_PALETTE_COLORS of them fit the u4 bitfield
+    if scylla
+        .palette(&app_data.canvas_id)?
+        .contains_color(req.color)
+    {
         if req.loc.x < app_data.canvas_dim && req.loc.y < app_data.canvas_dim {
-            if let Some(address) = req.address.clone() {
-                let time_diff: i64 = diff_last_placed(&address, app_data.cooldown, &scylla).await?;
-                let cd = i64::try_from(app_data.cooldown).map_err(VpError::ParseIntErr)?;
-                if time_diff.ge(&cd) {
-                    let offset: u32 = req.loc.x * app_data.canvas_dim + req.loc.y;
-                    // set redis bitmap
-                    let redis_fut = async {
-                        redis::cmd("bitfield")
-                            .arg(app_data.canvas_id.as_bytes())
-                            .arg("SET")
-                            .arg("u4")
-                            .arg(format!("#{}", offset))
-                            .arg(req.color)
-                            .query_async::<_, ()>(&mut conn)
-                            .await
-                    }
-                    .map_err(VpError::RedisErr);
-                    // update user timestamp in scylladb
-                    //also update pixeldata : )
-                    let scylla_fut = scylla.update_db(&req);
-
-                    //execute both  database fut : )
-                    tokio::try_join!(redis_fut, scylla_fut)?;
-                    // address not send to client : )
-                    // pixel based query will be added as different endpoint : )
-                    pu_srv.do_send(UpdatePixel {
-                        address: None,
-                        loc: req.loc,
-                        color: req.color,
-                    });
-                    Ok(Either::Left(HttpResponse::Ok()))
-                } else {
-                    Ok(Either::Right(HttpResponse::Forbidden().json(WaitTime {
-                        rem_wait: cd - time_diff,
-                    })))
-                }
+            if req.address.is_some() {
+                verify_signed(&req)?;
+                // update user timestamp in scylladb
+                //also update pixeldata : )
+                // cooldown is checked by scylla before writing
+                scylla.update_db(&app_data.canvas_id, &req).await?;
+                let offset: u32 = req.loc.x * app_data.canvas_dim + req.loc.y;
+                // set redis bitmap
+                redis::cmd("bitfield")
+                    .arg(app_data.canvas_id.as_bytes())
+                    .arg("SET")
+                    .arg("u4")
+                    .arg(format!("#{}", offset))
+                    .arg(req.color)
+                    .query_async::<_, ()>(&mut conn)
+                    .await
+                    .map_err(VpError::RedisErr)?;
+                // websocket clients are fed by scylla.subscribe() : )
+                Ok(HttpResponse::Ok())
             } else {
                 Err(VpError::InvalidUser)?
             }
@@ -129,6 +722,411 @@ async fn update_pixel(
     }
 }
 
+// erase a pixel you own, signed with erase_message. color of the body is ignored
+#[post("/pixel/erase")]
+async fn erase_pixel(
+    http_req: HttpRequest,
+    erase_req: web::Json<UpdatePixel>,
+    app_data: web::Data<AppState<'_>>,
+    redis: web::Data<Client>,
+    scylla: web::Data<ScyllaManager>,
+    limiter: web::Data<dyn RateLimiter>,
+) -> actix_web::Result<impl Responder> {
+    limiter.acquire(&client_key(&http_req), 1).await?;
+    let req = erase_req.into_inner();
+    if req.loc.x >= app_data.canvas_dim || req.loc.y >= app_data.canvas_dim {
+        Err(VpError::CanvasSizeMismatch)?
+    }
+    let (signature, nonce) = req
+        .signature
+        .as_ref()
+        .zip(req.nonce)
+        .ok_or(VpError::SignatureMismatch)?;
+    let signature =
+        hex::decode(signature.trim_start_matches("0x")).map_err(|_| VpError::SignatureMismatch)?;
+    verify_placement(&req, &signature, &erase_message(&req, nonce))?;
+    scylla.erase_pixel(&app_data.canvas_id, &req).await?;
+    set_cell(&redis, &app_data, &req.loc, scylla.background_color()).await?;
+    Ok(HttpResponse::Ok())
+}
+
+// placement only applied if the pixel still has ?expected, 409 with applied false otherwise
+// slower than /pixel/update (LWT), meant for contested pixels
+#[post("/pixel/update/if")]
+async fn update_pixel_if(
+    http_req: HttpRequest,
+    update_req: web::Json<UpdatePixel>,
+    query: web::Query<ExpectedQuery>,
+    app_data: web::Data<AppState<'_>>,
+    redis: web::Data<Client>,
+    scylla: web::Data<ScyllaManager>,
+    limiter: web::Data<dyn RateLimiter>,
+) -> actix_web::Result<impl Responder> {
+    limiter.acquire(&client_key(&http_req), 1).await?;
+    let req = update_req.into_inner();
+    if req.loc.x >= app_data.canvas_dim || req.loc.y >= app_data.canvas_dim {
+        Err(VpError::CanvasSizeMismatch)?
+    }
+    verify_signed(&req)?;
+    let applied = scylla
+        .update_db_if(&app_data.canvas_id, &req, query.expected)
+        .await?;
+    if !applied {
+        return Ok(HttpResponse::Conflict().json(AppliedResponse { applied }));
+    }
+    set_cell(
+        &redis,
+        &app_data,
+        &req.loc,
+        i32::try_from(req.color).map_err(VpError::from)?,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(AppliedResponse { applied }))
+}
+
+// like /pixel/update, but the cooldown check and the player write are a single LWT
+// so concurrent placements of one wallet can't both get past the cooldown
+#[post("/pixel/update/atomic")]
+async fn update_pixel_atomic(
+    http_req: HttpRequest,
+    update_req: web::Json<UpdatePixel>,
+    app_data: web::Data<AppState<'_>>,
+    redis: web::Data<Client>,
+    scylla: web::Data<ScyllaManager>,
+    limiter: web::Data<dyn RateLimiter>,
+) -> actix_web::Result<impl Responder> {
+    limiter.acquire(&client_key(&http_req), 1).await?;
+    let req = update_req.into_inner();
+    if req.loc.x >= app_data.canvas_dim || req.loc.y >= app_data.canvas_dim {
+        Err(VpError::CanvasSizeMismatch)?
+    }
+    verify_signed(&req)?;
+    scylla.place_atomic(&app_data.canvas_id, &req).await?;
+    set_cell(
+        &redis,
+        &app_data,
+        &req.loc,
+        i32::try_from(req.color).map_err(VpError::from)?,
+    )
+    .await?;
+    Ok(HttpResponse::Ok())
+}
+
+// one cell of the redis canvas bitfield
+async fn set_cell(
+    redis: &Client,
+    app_data: &AppState<'_>,
+    loc: &PixelLoc,
+    color: i32,
+) -> Result<(), VpError> {
+    let mut conn = redis.get_tokio_connection_manager().await?;
+    let offset: u32 = loc.x * app_data.canvas_dim + loc.y;
+    redis::cmd("bitfield")
+        .arg(app_data.canvas_id.as_bytes())
+        .arg("SET")
+        .arg("u4")
+        .arg(format!("#{}", offset))
+        .arg(color)
+        .query_async::<_, ()>(&mut conn)
+        .await?;
+    Ok(())
+}
+
+#[post("/pixel/batch")]
+async fn update_pixel_batch(
+    http_req: HttpRequest,
+    update_req: web::Json<Vec<UpdatePixel>>,
+    app_data: web::Data<AppState<'_>>,
+    redis: web::Data<Client>,
+    scylla: web::Data<ScyllaManager>,
+    limiter: web::Data<dyn RateLimiter>,
+) -> actix_web::Result<impl Responder> {
+    let reqs = update_req.into_inner();
+    // every pixel of the stamp takes a token
+    let tokens = u32::try_from(reqs.len()).unwrap_or(u32::MAX);
+    limiter.acquire(&client_key(&http_req), tokens).await?;
+    let address = match reqs.first() {
+        Some(req) => req.address.clone().ok_or(VpError::InvalidUser)?,
+        None => return Ok(HttpResponse::Ok()),
+    };
+    // a batch is a single stamp from a single user : )
+    if reqs
+        .iter()
+        .any(|req| req.address.as_ref() != Some(&address))
+    {
+        Err(VpError::InvalidUser)?
+    }
+    reqs.iter().try_for_each(verify_signed)?;
+    let palette = scylla.palette(&app_data.canvas_id)?;
+    if reqs.iter().any(|req| !palette.contains_color(req.color)) {
+        Err(VpError::ColorSizeMismatch)?
+    }
+    if reqs
+        .iter()
+        .any(|req| req.loc.x >= app_data.canvas_dim || req.loc.y >= app_data.canvas_dim)
+    {
+        Err(VpError::CanvasSizeMismatch)?
+    }
+    // cooldown is checked by scylla before writing
+    scylla.update_db_batch(&app_data.canvas_id, &reqs).await?;
+    let mut conn = redis
+        .get_tokio_connection_manager()
+        .await
+        .map_err(VpError::RedisErr)?;
+    // single bitfield command with one SET per pixel
+    let mut bitfield = redis::cmd("bitfield");
+    bitfield.arg(app_data.canvas_id.as_bytes());
+    reqs.iter().for_each(|req| {
+        let offset: u32 = req.loc.x * app_data.canvas_dim + req.loc.y;
+        bitfield
+            .arg("SET")
+            .arg("u4")
+            .arg(format!("#{}", offset))
+            .arg(req.color);
+    });
+    bitfield
+        .query_async::<_, ()>(&mut conn)
+        .await
+        .map_err(VpError::RedisErr)?;
+    Ok(HttpResponse::Ok())
+}
+
+// admin routes need the X-Admin-Token header
+fn require_admin(req: &HttpRequest, app_data: &AppState<'_>) -> Result<(), VpError> {
+    let token = req
+        .headers()
+        .get("X-Admin-Token")
+        .and_then(|t| t.to_str().ok());
+    match (&app_data.admin_token, token) {
+        (Some(admin), Some(token)) if admin == token => Ok(()),
+        _ => Err(VpError::NotAdmin),
+    }
+}
+
+// placement must be signed by req.address
+fn verify_signed(req: &UpdatePixel) -> Result<(), VpError> {
+    let (signature, nonce) = req
+        .signature
+        .as_ref()
+        .zip(req.nonce)
+        .ok_or(VpError::SignatureMismatch)?;
+    let signature =
+        hex::decode(signature.trim_start_matches("0x")).map_err(|_| VpError::SignatureMismatch)?;
+    verify_placement(req, &signature, &placement_message(req, nonce))
+}
+
+// maintenance mode, body {"readOnly": true} blocks every write until set back to false
+#[post("/admin/read_only")]
+async fn set_read_only(
+    req: HttpRequest,
+    body: web::Json<ReadOnly>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    require_admin(&req, &app_data)?;
+    scylla.set_read_only(body.read_only);
+    log::warn!(
+        "Read only mode {}",
+        if body.read_only { "on" } else { "off" }
+    );
+    Ok(HttpResponse::Ok().json(ReadOnly {
+        read_only: scylla.is_read_only(),
+    }))
+}
+
+// painted pixels per partition, reads every partition : )
+#[get("/admin/partitions")]
+async fn partition_stats(
+    req: HttpRequest,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    require_admin(&req, &app_data)?;
+    let res = scylla.partition_stats(&app_data.canvas_id).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+// true once a canvas_part grew past RESHARD_THRESHOLD
+#[get("/admin/resharding")]
+async fn resharding_hint(
+    req: HttpRequest,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    require_admin(&req, &app_data)?;
+    Ok(HttpResponse::Ok().json(ReshardingResponse {
+        needs_resharding: scylla.needs_resharding(),
+    }))
+}
+
+// canvas_part names with the x / y ranges they cover, for rebalancing and monitoring
+#[get("/admin/partitions/ranges")]
+async fn partition_ranges(
+    req: HttpRequest,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    require_admin(&req, &app_data)?;
+    Ok(HttpResponse::Ok().json(scylla.partitions(&app_data.canvas_id)?))
+}
+
+// newline delimited json of one canvas_part, like /canvas/snapshot
+#[get("/admin/partitions/{part}/dump")]
+async fn dump_partition(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<DumpQuery>,
+    app_data: web::Data<AppState<'_>>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    require_admin(&req, &app_data)?;
+    let page_size = query.page_size.unwrap_or(1000).clamp(1, 10000);
+    let rows = scylla
+        .dump_partition(&path.into_inner(), page_size)?
+        .map_ok(|row| {
+            let mut line = serde_json::to_vec(&row).unwrap_or_default();
+            line.push(b'\n');
+            web::Bytes::from(line)
+        });
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(rows))
+}
+
+#[post("/admin/region/{x0}/{y0}/{x1}/{y1}/clear")]
+async fn clear_region(
+    req: HttpRequest,
+    path: web::Path<(u32, u32, u32, u32)>,
+    app_data: web::Data<AppState<'_>>,
+    redis: web::Data<Client>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    require_admin(&req, &app_data)?;
+    let (x0, y0, x1, y1) = path.into_inner();
+    if ![x0, y0, x1, y1].iter().all(|c| *c < app_data.canvas_dim) {
+        Err(VpError::CanvasSizeMismatch)?
+    }
+    let cleared = scylla
+        .clear_region(&app_data.canvas_id, x0, y0, x1, y1)
+        .await?;
+    let mut conn = redis
+        .get_tokio_connection_manager()
+        .await
+        .map_err(VpError::RedisErr)?;
+    // reset the region in the redis canvas to the background color, like an erase
+    let background = scylla.background_color();
+    let mut bitfield = redis::cmd("bitfield");
+    bitfield.arg(app_data.canvas_id.as_bytes());
+    for x in x0..=x1 {
+        for y in y0..=y1 {
+            let offset: u32 = x * app_data.canvas_dim + y;
+            bitfield
+                .arg("SET")
+                .arg("u4")
+                .arg(format!("#{}", offset))
+                .arg(background);
+        }
+    }
+    bitfield
+        .query_async::<_, ()>(&mut conn)
+        .await
+        .map_err(VpError::RedisErr)?;
+    log::info!(
+        "Admin cleared region ({},{})-({},{}) : {} pixels",
+        x0,
+        y0,
+        x1,
+        y1,
+        cleared
+    );
+    Ok(HttpResponse::Ok().json(ClearResponse { cleared }))
+}
+
+#[post("/admin/pixel/{x}/{y}/revert")]
+async fn revert_pixel(
+    req: HttpRequest,
+    path: web::Path<(u32, u32)>,
+    app_data: web::Data<AppState<'_>>,
+    redis: web::Data<Client>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    require_admin(&req, &app_data)?;
+    let (x, y) = path.into_inner();
+    let previous = scylla.revert_pixel(&app_data.canvas_id, x, y).await?;
+    // a pixel without a prior placement is unpainted again
+    let color = previous
+        .as_ref()
+        .map_or(scylla.background_color(), |pixel| pixel.color);
+    set_cell(&redis, &app_data, &PixelLoc { x, y }, color).await?;
+    log::info!("Admin reverted pixel ({},{})", x, y);
+    match previous {
+        Some(pixel) => Ok(HttpResponse::Ok().json(pixel)),
+        None => Ok(HttpResponse::NoContent().finish()),
+    }
+}
+
+// bans a wallet's contributions, pixels painted over since are kept
+#[post("/admin/user/{address}/remove")]
+async fn remove_user_pixels(
+    req: HttpRequest,
+    path: web::Path<String>,
+    app_data: web::Data<AppState<'_>>,
+    redis: web::Data<Client>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    require_admin(&req, &app_data)?;
+    let address = path.into_inner();
+    let cleared = scylla.remove_user_pixels(&address).await?;
+    sync_place(&app_data, &redis, &scylla).await?;
+    log::info!("Admin removed {} pixels of {}", cleared, address);
+    Ok(HttpResponse::Ok().json(ClearResponse { cleared }))
+}
+
+// body is the png to seed the canvas with, every cell is attributed to ?address
+#[post("/admin/canvas/import")]
+async fn import_canvas(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: web::Query<ImportQuery>,
+    app_data: web::Data<AppState<'_>>,
+    redis: web::Data<Client>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    require_admin(&req, &app_data)?;
+    let canvas_id = &app_data.canvas_id;
+    let imported = scylla
+        .import_png(canvas_id, &body, scylla.palette(canvas_id)?, &query.address)
+        .await?;
+    sync_place(&app_data, &redis, &scylla).await?;
+    log::info!("Admin imported {} pixels", imported);
+    Ok(HttpResponse::Ok().json(ImportResponse { imported }))
+}
+
+// DEV ONLY : ?count random placements, ?seed makes runs reproducible
+#[cfg(feature = "seed")]
+#[post("/admin/canvas/seed")]
+pub async fn seed_canvas(
+    req: HttpRequest,
+    query: web::Query<crate::models::p_models::SeedQuery>,
+    app_data: web::Data<AppState<'_>>,
+    redis: web::Data<Client>,
+    scylla: web::Data<ScyllaManager>,
+) -> actix_web::Result<impl Responder> {
+    require_admin(&req, &app_data)?;
+    let canvas_id = &app_data.canvas_id;
+    scylla
+        .seed_random(
+            canvas_id,
+            query.count,
+            scylla.palette(canvas_id)?,
+            query.seed.unwrap_or(0),
+        )
+        .await?;
+    sync_place(&app_data, &redis, &scylla).await?;
+    log::info!("Admin seeded {} random pixels", query.count);
+    Ok(HttpResponse::Ok())
+}
+
 // websocket handlers
 impl<'a> StreamHandler<Result<ws::Message, ws::ProtocolError>> for VpListener<'a> {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
@@ -174,6 +1172,19 @@ impl Handler<UpdatePixel> for VpSrv<'_> {
     }
 }
 
+impl Handler<PixelBatch> for VpSrv<'_> {
+    type Result = ();
+
+    fn handle(&mut self, msg: PixelBatch, _ctx: &mut Self::Context) -> Self::Result {
+        if let Ok(res) = serde_json::to_string(&msg) {
+            let msg = Cow::from(res);
+            self.listeners
+                .iter()
+                .for_each(|addr| addr.do_send(VpRes(msg.clone())));
+        }
+    }
+}
+
 impl Handler<VpRes<'_>> for VpListener<'_> {
     type Result = ();
 
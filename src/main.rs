@@ -2,19 +2,41 @@ mod handlers;
 mod models;
 mod services;
 use std::env;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
 
 use actix::Actor;
 use actix_cors::Cors;
 use actix_web::middleware::Logger;
 use actix_web::{web, App, HttpServer};
 use dotenvy::dotenv;
-use handlers::p_handlers::get_canvas;
+use handlers::p_handlers::{canvas_info, canvas_modified, canvas_png, canvas_snapshot, get_canvas};
 use mimalloc::MiMalloc;
+use scylla::transport::session::PoolSize;
+use scylla::transport::Compression;
 
-use crate::handlers::p_handlers::{opbnbplace, pixel_info, update_pixel};
-use crate::models::p_models::{AppState, VpSrv};
-use crate::models::scylla_models::ScyllaBuilder;
-use crate::services::p_services::init_place;
+#[cfg(feature = "seed")]
+use crate::handlers::p_handlers::seed_canvas;
+use crate::handlers::p_handlers::{
+    batch_read, canvas_mipmap, clear_region, color_histogram, delta_since, dump_partition,
+    erase_pixel, explain_pixel, fill_percentage, healthz, heatmap, import_canvas, largest_region,
+    leaderboard, opbnbplace, partition_ranges, partition_stats, pixel_history, pixel_history_page,
+    pixel_info, pixel_neighborhood, pixel_owner, pixel_rgb, pixels_info, recent_placements,
+    region_checksum, region_count, region_info, region_packed, region_timelapse,
+    remove_user_pixels, resharding_hint, revert_pixel, set_read_only, team_scores, top_colors,
+    total_placements, update_pixel, update_pixel_atomic, update_pixel_batch, update_pixel_if,
+    user_cooldown, user_history_csv, user_info, user_pixel, user_recent, user_region, users_info,
+    validate_pixel,
+};
+use crate::models::p_models::{AppState, Palette, VpSrv};
+use crate::models::scylla_models::{
+    CanvasConfig, ColorMode, ConnectRetry, CooldownScaling, DbConsistency, InkBudget, PaletteCheck,
+    Rect, ReplicationConfig, ReplicationStrategy, RetryConfig, ScyllaBuilder, SessionOptions,
+};
+use crate::services::event_services::RedisEventSink;
+use crate::services::p_services::{forward_updates, init_place};
+use crate::services::rate_services::{MemoryRateLimiter, RateLimiter};
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
@@ -29,22 +51,359 @@ async fn main() -> std::io::Result<()> {
     let port = env::var("PORT").unwrap_or_else(|_| "8080".to_string());
     let redis_url = env::var("REDIS_URL").unwrap_or_else(|_| "redis://0.0.0.0:6379".to_string());
     let scylla_url = env::var("SCYLLA_URL").unwrap_or_else(|_| "0.0.0.0:9042".to_string());
+    let keyspace = env::var("SCYLLA_KEYSPACE").unwrap_or_else(|_| "opbnbplace".to_string());
     let canvas_dim =
         env::var("CANVAS_DIM").map_or(500, |count| count.parse::<u32>().unwrap_or(500));
     let canvas_id = env::var("CANVAS_ID").unwrap_or_else(|_| "opbnbplace_1".to_string());
-    let cooldown = env::var("COOLDOWN").map_or(60, |c| c.parse::<usize>().unwrap_or(60));
+    let canvas_shards =
+        env::var("CANVAS_SHARDS").map_or(2, |count| count.parse::<u32>().unwrap_or(2));
+    // more canvases in the same keyspace, id:dim:shards[:truecolor] comma separated
+    // eg: event_1:256:2,event_2:100:1:truecolor , the others share the palette of the default canvas
+    let extra_canvases: Vec<(String, u32, u32, bool)> = env::var("EXTRA_CANVASES").map_or_else(
+        |_| Vec::new(),
+        |canvases| {
+            canvases
+                .split(',')
+                .filter_map(|canvas| {
+                    let mut parts = canvas.trim().split(':');
+                    let id = parts.next().filter(|id| !id.is_empty())?;
+                    let dim = parts.next()?.parse::<u32>().ok()?;
+                    let shards = parts.next().map_or(Some(2), |s| s.parse::<u32>().ok())?;
+                    let truecolor = parts.next() == Some("truecolor");
+                    Some((id.to_string(), dim, shards, truecolor))
+                })
+                .collect()
+        },
+    );
+    // history rows of one canvas_part before ops get the resharding hint
+    let reshard_threshold =
+        env::var("RESHARD_THRESHOLD").map_or(1_000_000, |t| t.parse::<u64>().unwrap_or(1_000_000));
+    let cooldown = env::var("COOLDOWN").map_or(60, |c| c.parse::<i64>().unwrap_or(60));
+    // placements of a new wallet that skip the cooldown
+    let free_placements = env::var("FREE_PLACEMENTS").map_or(0, |f| f.parse::<u32>().unwrap_or(0));
+    // placements per wallet and utc day, 0 is unlimited
+    let daily_quota = env::var("DAILY_QUOTA").map_or(0, |q| q.parse::<u32>().unwrap_or(0));
+    // admin only rectangles of the canvas, x0:y0:x1:y1 inclusive, comma separated
+    let reserved_regions: Vec<Rect> = env::var("RESERVED_REGIONS").map_or_else(
+        |_| Vec::new(),
+        |regions| {
+            regions
+                .split(',')
+                .filter_map(|region| {
+                    let c = region
+                        .trim()
+                        .split(':')
+                        .map(|c| c.parse::<u32>().ok())
+                        .collect::<Option<Vec<_>>>()?;
+                    match c[..] {
+                        [x0, y0, x1, y1] => Some(Rect::new(x0, y0, x1, y1)),
+                        _ => None,
+                    }
+                })
+                .collect()
+        },
+    );
+    // cooldown secs per region, eg: 0:0:99:99:60,40:40:59:59:5
+    let region_cooldowns: Vec<(Rect, i64)> = env::var("REGION_COOLDOWNS").map_or_else(
+        |_| Vec::new(),
+        |regions| {
+            regions
+                .split(',')
+                .filter_map(|region| {
+                    let c = region
+                        .trim()
+                        .split(':')
+                        .map(|c| c.parse::<u32>().ok())
+                        .collect::<Option<Vec<_>>>()?;
+                    match c[..] {
+                        [x0, y0, x1, y1, secs] => {
+                            Some((Rect::new(x0, y0, x1, y1), i64::from(secs)))
+                        }
+                        _ => None,
+                    }
+                })
+                .collect()
+        },
+    );
+    // daily placements per color, eg: 5:1000,3:500
+    let color_quotas: Vec<(i32, u32)> = env::var("COLOR_QUOTAS").map_or_else(
+        |_| Vec::new(),
+        |quotas| {
+            quotas
+                .split(',')
+                .filter_map(|quota| {
+                    let (color, quota) = quota.trim().split_once(':')?;
+                    Some((color.parse().ok()?, quota.parse().ok()?))
+                })
+                .collect()
+        },
+    );
+    // cooldown * factor^streak for users placing back to back, unset keeps it flat
+    let cooldown_scaling = env::var("COOLDOWN_FACTOR")
+        .ok()
+        .and_then(|f| f.parse::<f64>().ok())
+        .map(|factor| CooldownScaling {
+            factor,
+            max_secs: env::var("COOLDOWN_MAX").map_or(600, |m| m.parse::<i64>().unwrap_or(600)),
+            reset_after_secs: env::var("COOLDOWN_RESET_AFTER")
+                .map_or(300, |r| r.parse::<i64>().unwrap_or(300)),
+        });
+    // ink budget instead of the cooldown, on when INK_MAX is set
+    let ink = env::var("INK_MAX")
+        .ok()
+        .and_then(|m| m.parse::<i64>().ok())
+        .map(|max| InkBudget {
+            max,
+            cost: env::var("INK_COST").map_or(1, |c| c.parse::<i64>().unwrap_or(1)),
+            refill_secs: env::var("INK_REFILL_SECS").map_or(30, |r| r.parse::<i64>().unwrap_or(30)),
+        });
+    // at most 16 colors (MAX_PALETTE_COLORS), the redis canvas can't hold more
+    let palette = env::var("PALETTE").map_or_else(
+        |_| Palette::default(),
+        |p| {
+            Palette::parse(&p)
+                .filter(Palette::fits_bitfield)
+                .expect("PALETTE needs 1 to 16 hex colors")
+        },
+    );
+    let replication = ReplicationConfig {
+        strategy: match env::var("SCYLLA_REPLICATION_STRATEGY").as_deref() {
+            Ok("SimpleStrategy") => ReplicationStrategy::Simple,
+            _ => ReplicationStrategy::NetworkTopology,
+        },
+        replication_factor: env::var("SCYLLA_REPLICATION_FACTOR")
+            .map_or(1, |rf| rf.parse::<u32>().unwrap_or(1)),
+        // dc1:3,dc2:2
+        datacenters: env::var("SCYLLA_DATACENTERS").map_or_else(
+            |_| Vec::new(),
+            |dcs| {
+                dcs.split(',')
+                    .filter_map(|dc| {
+                        let (name, rf) = dc.split_once(':')?;
+                        Some((name.trim().to_string(), rf.trim().parse::<u32>().ok()?))
+                    })
+                    .collect()
+            },
+        ),
+    };
+    // seconds a pixel stays in the redis read cache, 0 disables the cache
+    let pixel_cache_ttl =
+        env::var("PIXEL_CACHE_TTL").map_or(5, |ttl| ttl.parse::<usize>().unwrap_or(5));
+    let health_timeout =
+        env::var("HEALTH_TIMEOUT_MS").map_or(2000, |t| t.parse::<u64>().unwrap_or(2000));
+    let op_timeout =
+        env::var("DB_OP_TIMEOUT_MS").map_or(10000, |t| t.parse::<u64>().unwrap_or(10000));
+    let retry = RetryConfig {
+        max_attempts: env::var("SCYLLA_RETRY_ATTEMPTS")
+            .map_or(3, |a| a.parse::<u32>().unwrap_or(3)),
+        base_delay: Duration::from_millis(
+            env::var("SCYLLA_RETRY_DELAY_MS").map_or(50, |d| d.parse::<u64>().unwrap_or(50)),
+        ),
+        jitter: Duration::from_millis(
+            env::var("SCYLLA_RETRY_JITTER_MS").map_or(25, |j| j.parse::<u64>().unwrap_or(25)),
+        ),
+    };
+    let read_consistency = env::var("SCYLLA_READ_CONSISTENCY")
+        .ok()
+        .and_then(|c| DbConsistency::parse(&c));
+    let write_consistency = env::var("SCYLLA_WRITE_CONSISTENCY")
+        .ok()
+        .and_then(|c| DbConsistency::parse(&c));
+    // check stored colors against the palette on startup : warn / error
+    let palette_check = env::var("VERIFY_PALETTE")
+        .ok()
+        .and_then(|c| PaletteCheck::parse(&c));
+    let page_size = env::var("SCYLLA_PAGE_SIZE").map_or(5000, |p| p.parse::<i32>().unwrap_or(5000));
+    // seconds before a placed pixel expires, 0 keeps pixels forever
+    let pixel_ttl = env::var("PIXEL_TTL").map_or(0, |t| t.parse::<u32>().unwrap_or(0));
+    // record db metrics through the `metrics` facade
+    let db_metrics = env::var("SCYLLA_METRICS").is_ok_and(|m| m == "true" || m == "1");
+    // background of unpainted cells in rendered images, eg: FFFFFF
+    let render_background = env::var("CANVAS_BACKGROUND")
+        .ok()
+        .and_then(|c| Palette::parse(&c))
+        .and_then(|c| c.colors.first().copied())
+        .unwrap_or([0xFF, 0xFF, 0xFF]);
+    // palette index of unpainted cells in region reads
+    let background_color =
+        env::var("CANVAS_BACKGROUND_COLOR").map_or(0, |c| c.parse::<i32>().unwrap_or(0));
+    // redis pub/sub channel placement events are published on, unset disables them
+    let event_channel = env::var("EVENT_CHANNEL").ok().filter(|c| !c.is_empty());
+    // websocket updates within this many ms are sent as one batch, unset / 0 sends each at once
+    let ws_coalesce = env::var("WS_COALESCE_MS")
+        .ok()
+        .and_then(|ms| ms.parse::<u64>().ok())
+        .filter(|ms| *ms > 0)
+        .map(Duration::from_millis);
+    // pixel updates buffered per websocket feed subscriber
+    let update_capacity =
+        env::var("PIXEL_UPDATE_CAPACITY").map_or(1024, |c| c.parse::<usize>().unwrap_or(1024));
+    // comma separated wallets placing without cooldown
+    let admin_addresses = env::var("ADMIN_ADDRESSES").map_or_else(
+        |_| Vec::new(),
+        |a| {
+            a.split(',')
+                .map(|a| a.trim().to_string())
+                .filter(|a| !a.is_empty())
+                .collect()
+        },
+    );
+    // comma separated teams placements may claim, eg: red,blue
+    let teams = env::var("TEAMS").map_or_else(
+        |_| Vec::new(),
+        |t| {
+            t.split(',')
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty())
+                .collect()
+        },
+    );
+    let connect_retry = ConnectRetry {
+        max_attempts: env::var("SCYLLA_CONNECT_ATTEMPTS")
+            .map_or(10, |a| a.parse::<u32>().unwrap_or(10)),
+        delay: Duration::from_millis(
+            env::var("SCYLLA_CONNECT_DELAY_MS").map_or(3000, |d| d.parse::<u64>().unwrap_or(3000)),
+        ),
+    };
+    let session_options = SessionOptions {
+        connection_timeout: env::var("SCYLLA_CONNECTION_TIMEOUT_MS")
+            .ok()
+            .and_then(|t| t.parse::<u64>().ok())
+            .map(Duration::from_millis),
+        request_timeout: env::var("SCYLLA_REQUEST_TIMEOUT_MS")
+            .ok()
+            .and_then(|t| t.parse::<u64>().ok())
+            .map(Duration::from_millis),
+        compression: env::var("SCYLLA_COMPRESSION").ok().and_then(|c| {
+            match c.to_lowercase().as_str() {
+                "lz4" => Some(Compression::Lz4),
+                "snappy" => Some(Compression::Snappy),
+                _ => None,
+            }
+        }),
+        // SCYLLA_POOL_PER_HOST wins over SCYLLA_POOL_PER_SHARD when both are set
+        pool_size: match (
+            env::var("SCYLLA_POOL_PER_HOST")
+                .ok()
+                .and_then(|p| p.parse::<NonZeroUsize>().ok()),
+            env::var("SCYLLA_POOL_PER_SHARD")
+                .ok()
+                .and_then(|p| p.parse::<NonZeroUsize>().ok()),
+        ) {
+            (Some(per_host), _) => PoolSize::PerHost(per_host),
+            (None, Some(per_shard)) => PoolSize::PerShard(per_shard),
+            (None, None) => SessionOptions::default().pool_size,
+        },
+        disallow_shard_aware_port: env::var("SCYLLA_DISALLOW_SHARD_AWARE_PORT")
+            .is_ok_and(|d| d == "true" || d == "1"),
+    };
+    // seconds an idempotency key of a placement is remembered
+    let idempotency_window =
+        env::var("IDEMPOTENCY_WINDOW").map_or(300, |w| w.parse::<u64>().unwrap_or(300));
+    // placements per client ip : burst size and tokens refilled per second
+    let rate_limit_burst =
+        env::var("RATE_LIMIT_BURST").map_or(20, |b| b.parse::<u32>().unwrap_or(20));
+    let rate_limit_refill =
+        env::var("RATE_LIMIT_REFILL").map_or(2.0, |r| r.parse::<f64>().unwrap_or(2.0));
+    let admin_token = env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty());
     let host_port = format!("{}:{}", host, port);
     let redis_client = redis::Client::open(redis_url).expect("Error connecting to RedisDB");
-    let redis = web::Data::new(redis_client);
-    let scylla_man = ScyllaBuilder::try_init(&scylla_url, canvas_dim)
-        .await
-        .expect("Error initiating ScyllaBuilder")
+    let mut scylla_builder =
+        ScyllaBuilder::try_init(&scylla_url, canvas_dim, replication, connect_retry)
+            .await
+            .expect("Error initiating ScyllaBuilder")
+            .with_options(session_options)
+            .with_keyspace(&keyspace)
+            .with_pixel_cache(redis_client.clone(), pixel_cache_ttl)
+            .with_health_timeout(Duration::from_millis(health_timeout))
+            .with_op_timeout(Duration::from_millis(op_timeout))
+            .with_retry(retry)
+            .with_page_size(page_size)
+            .with_pixel_ttl(pixel_ttl)
+            .with_metrics(db_metrics)
+            .with_render_background(render_background)
+            .with_background_color(background_color)
+            .with_update_capacity(update_capacity)
+            .with_reshard_threshold(reshard_threshold)
+            .with_idempotency_window(Duration::from_secs(idempotency_window))
+            .with_shards(canvas_shards)
+            .with_cooldown(cooldown)
+            .with_free_placements(free_placements)
+            .with_daily_quota(daily_quota)
+            .with_color_quotas(color_quotas)
+            .with_reserved_regions(reserved_regions)
+            .with_region_cooldowns(region_cooldowns)
+            .with_canvas_id(&canvas_id)
+            .with_admin_addresses(admin_addresses)
+            .with_teams(teams)
+            .with_palette(palette.clone());
+    for (id, dim, shards, truecolor) in extra_canvases {
+        log::debug!("Extra canvas {} : {}x{}", id, dim, dim);
+        scylla_builder = scylla_builder.with_canvas(
+            &id,
+            CanvasConfig {
+                canvas_dim: dim,
+                shards,
+                color_mode: if truecolor {
+                    ColorMode::Truecolor
+                } else {
+                    ColorMode::Palette(palette.clone())
+                },
+                reserved_regions: Vec::new(),
+                region_cooldowns: Vec::new(),
+            },
+        );
+    }
+    if let Some(channel) = event_channel {
+        let sink = RedisEventSink::new(redis_client.clone(), &channel)
+            .await
+            .expect("Error connecting the event sink");
+        scylla_builder = scylla_builder.with_event_sink(Arc::new(sink));
+    }
+    if let Some(scaling) = cooldown_scaling {
+        scylla_builder = scylla_builder.with_cooldown_scaling(scaling);
+    }
+    if let Some(ink) = ink {
+        scylla_builder = scylla_builder.with_ink_budget(ink);
+    }
+    if let Some(level) = read_consistency {
+        scylla_builder = scylla_builder.with_read_consistency(level);
+    }
+    if let Some(level) = write_consistency {
+        scylla_builder = scylla_builder.with_write_consistency(level);
+    }
+    if let Some(check) = palette_check {
+        scylla_builder = scylla_builder.with_palette_check(check);
+    }
+    let scylla_man = scylla_builder
         .try_build()
         .await
         .expect("Unable to Build ScyllaManger");
+    let redis = web::Data::new(redis_client);
     let scylla = web::Data::new(scylla_man);
-    let app_state = web::Data::new(AppState::new(canvas_id.into(), canvas_dim, cooldown));
+    let app_state = web::Data::new(AppState::new(
+        canvas_id.clone().into(),
+        canvas_dim,
+        admin_token,
+    ));
+    let rate_limiter = Arc::new(MemoryRateLimiter::new(rate_limit_burst, rate_limit_refill));
+    let pruned = rate_limiter.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            pruned.prune();
+        }
+    });
+    let rate_limiter: web::Data<dyn RateLimiter> =
+        web::Data::from(rate_limiter as Arc<dyn RateLimiter>);
     let vp_srv = VpSrv::new().start();
+    actix_web::rt::spawn(forward_updates(
+        scylla.subscribe(),
+        canvas_id.clone(),
+        vp_srv.clone(),
+        ws_coalesce,
+    ));
     init_place(&app_state, &redis)
         .await
         .expect("Error Initialising Canvas");
@@ -56,8 +415,9 @@ async fn main() -> std::io::Result<()> {
     );
     log::info!("opbnb-place server listening on : {}", host_port);
     let cpus = num_cpus::get();
-    HttpServer::new(move || {
-        App::new()
+    let scylla_shutdown = scylla.clone();
+    let server = HttpServer::new(move || {
+        let app = App::new()
             .wrap(Logger::default())
             // use only in testing : )
             .wrap(Cors::permissive())
@@ -65,13 +425,71 @@ async fn main() -> std::io::Result<()> {
             .app_data(web::Data::new(vp_srv.clone()))
             .app_data(redis.clone())
             .app_data(scylla.clone())
+            .app_data(rate_limiter.clone())
+            .service(healthz)
             .service(opbnbplace)
             .service(get_canvas)
+            .service(canvas_snapshot)
+            .service(canvas_info)
+            .service(canvas_png)
+            .service(canvas_mipmap)
+            .service(canvas_modified)
             .service(update_pixel)
+            .service(update_pixel_if)
+            .service(update_pixel_atomic)
+            .service(validate_pixel)
+            .service(explain_pixel)
+            .service(update_pixel_batch)
+            .service(erase_pixel)
             .service(pixel_info)
+            .service(clear_region)
+            .service(partition_stats)
+            .service(resharding_hint)
+            .service(partition_ranges)
+            .service(set_read_only)
+            .service(dump_partition)
+            .service(revert_pixel)
+            .service(remove_user_pixels)
+            .service(total_placements)
+            .service(team_scores)
+            .service(fill_percentage)
+            .service(top_colors)
+            .service(largest_region)
+            .service(color_histogram)
+            .service(import_canvas)
+            .service(pixel_history)
+            .service(pixel_neighborhood)
+            .service(pixel_rgb)
+            .service(pixel_history_page)
+            .service(pixel_owner)
+            .service(leaderboard)
+            .service(recent_placements)
+            .service(delta_since)
+            .service(heatmap)
+            .service(pixels_info)
+            .service(region_info)
+            .service(region_count)
+            .service(region_checksum)
+            .service(region_timelapse)
+            .service(batch_read)
+            .service(region_packed)
+            .service(user_cooldown)
+            .service(user_info)
+            .service(user_recent)
+            .service(user_pixel)
+            .service(user_history_csv)
+            .service(user_region)
+            .service(users_info);
+        #[cfg(feature = "seed")]
+        let app = app.service(seed_canvas);
+        app
     })
     .bind(host_port)?
     .workers(cpus * 2)
     .run()
-    .await
+    .await;
+    // actix stopped on SIGTERM / SIGINT, let placements still writing finish : )
+    scylla_shutdown.shutdown().await;
+    log::info!("opbnb-place server stopped");
+    server
 }
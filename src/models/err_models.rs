@@ -2,24 +2,206 @@ use std::error::Error;
 use std::fmt::Display;
 use std::num::TryFromIntError;
 
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use image::ImageError;
 use redis::RedisError;
+use scylla::cql_to_rust::FromRowError;
 use scylla::transport::errors::{NewSessionError, QueryError};
+use scylla::transport::iterator::NextRowError;
 use scylla::transport::query_result::FirstRowTypedError;
 
+use super::p_models::WaitTime;
+
 #[derive(Debug)]
 pub enum VpError {
     InitCanvasErr,
+    InvalidKeyspace(String),
     RedisErr(RedisError),
     ColorSizeMismatch,
+    NoPalette(String),
     CanvasSizeMismatch,
     InvalidUser,
     ScyllaQueryErr(QueryError),
     ScyllaTypeErr(FirstRowTypedError),
+    ScyllaRowErr(FromRowError),
     ScyllaSessionErr(NewSessionError),
     ParseIntErr(TryFromIntError),
     NoPixelData,
+    RegionTooLarge,
+    CooldownActive {
+        remaining_secs: i64,
+    },
+    InsufficientInk {
+        available: i64,
+        required: i64,
+    },
+    SignatureMismatch,
+    InvalidColor(i32),
+    InvalidReplication(String),
+    OperationTimeout,
+    NotAdmin,
+    OutOfBounds {
+        x: u32,
+        y: u32,
+    },
+    InvalidRegion {
+        x0: u32,
+        y0: u32,
+        x1: u32,
+        y1: u32,
+    },
+    RenderError(ImageError),
+    DimensionMismatch {
+        width: u32,
+        height: u32,
+    },
+    BatchTooLarge,
+    PixelBatchTooLarge,
+    ReadBatchTooLarge,
+    TagTooLong,
+    TimelapseTooLarge,
+    UnknownCanvas(String),
+    UnknownTeam(String),
+    RateLimited {
+        retry_after: u64,
+    },
+    ShuttingDown,
+    ReadOnlyMode,
+    InvalidNonce {
+        expected: u64,
+    },
+    InvalidTimestamp(i64),
+    InvalidAddressFormat(String),
+    NotPixelOwner,
+    UnknownPartition(String),
+    ColorQuotaExceeded {
+        color: i32,
+    },
+    DailyQuotaExceeded {
+        placed: i64,
+        limit: i64,
+    },
+    PaletteMismatch {
+        canvas: String,
+        color: i32,
+        colors: usize,
+    },
+    RegionReserved {
+        x: u32,
+        y: u32,
+    },
+    DeltaTooOld,
+    // db failures with the pixel / user they happened on
+    PixelReadFailed {
+        x: u32,
+        y: u32,
+        source: Box<VpError>,
+    },
+    PixelWriteFailed {
+        x: u32,
+        y: u32,
+        source: Box<VpError>,
+    },
+    UserReadFailed {
+        address: String,
+        source: Box<VpError>,
+    },
+}
+impl Error for VpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use VpError::*;
+        match self {
+            PixelReadFailed { source, .. }
+            | PixelWriteFailed { source, .. }
+            | UserReadFailed { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+impl VpError {
+    // driver level failures, domain errors (NoPixelData, CooldownActive ..) are left as is
+    // so handlers can still match on them : )
+    fn is_db(&self) -> bool {
+        matches!(
+            self,
+            VpError::ScyllaQueryErr(_) | VpError::ScyllaTypeErr(_) | VpError::ScyllaRowErr(_)
+        )
+    }
+    fn with_context(self, wrap: impl FnOnce(Box<Self>) -> Self) -> Self {
+        if self.is_db() {
+            wrap(Box::new(self))
+        } else {
+            self
+        }
+    }
+    // map_err adapters, eg: .map_err(VpError::pixel_read(x, y))
+    pub fn pixel_read(x: u32, y: u32) -> impl FnOnce(Self) -> Self {
+        move |e| e.with_context(|source| VpError::PixelReadFailed { x, y, source })
+    }
+    pub fn pixel_write(x: u32, y: u32) -> impl FnOnce(Self) -> Self {
+        move |e| e.with_context(|source| VpError::PixelWriteFailed { x, y, source })
+    }
+    pub fn user_read(address: &str) -> impl FnOnce(Self) -> Self {
+        let address = address.to_string();
+        move |e| e.with_context(|source| VpError::UserReadFailed { address, source })
+    }
+    // variant name, used as a metrics label
+    pub fn name(&self) -> &'static str {
+        use VpError::*;
+        match self {
+            InitCanvasErr => "InitCanvasErr",
+            InvalidKeyspace(_) => "InvalidKeyspace",
+            RedisErr(_) => "RedisErr",
+            ColorSizeMismatch => "ColorSizeMismatch",
+            NoPalette(_) => "NoPalette",
+            CanvasSizeMismatch => "CanvasSizeMismatch",
+            InvalidUser => "InvalidUser",
+            ScyllaQueryErr(_) => "ScyllaQueryErr",
+            ScyllaTypeErr(_) => "ScyllaTypeErr",
+            ScyllaRowErr(_) => "ScyllaRowErr",
+            ScyllaSessionErr(_) => "ScyllaSessionErr",
+            ParseIntErr(_) => "ParseIntErr",
+            NoPixelData => "NoPixelData",
+            UnknownCanvas(_) => "UnknownCanvas",
+            UnknownTeam(_) => "UnknownTeam",
+            RateLimited { .. } => "RateLimited",
+            ShuttingDown => "ShuttingDown",
+            ReadOnlyMode => "ReadOnlyMode",
+            InvalidNonce { .. } => "InvalidNonce",
+            InvalidTimestamp(_) => "InvalidTimestamp",
+            InvalidAddressFormat(_) => "InvalidAddressFormat",
+            NotPixelOwner => "NotPixelOwner",
+            UnknownPartition(_) => "UnknownPartition",
+            ColorQuotaExceeded { .. } => "ColorQuotaExceeded",
+            DailyQuotaExceeded { .. } => "DailyQuotaExceeded",
+            PaletteMismatch { .. } => "PaletteMismatch",
+            RegionReserved { .. } => "RegionReserved",
+            DeltaTooOld => "DeltaTooOld",
+            PixelReadFailed { .. } => "PixelReadFailed",
+            PixelWriteFailed { .. } => "PixelWriteFailed",
+            UserReadFailed { .. } => "UserReadFailed",
+            RegionTooLarge => "RegionTooLarge",
+            CooldownActive { .. } => "CooldownActive",
+            InsufficientInk { .. } => "InsufficientInk",
+            SignatureMismatch => "SignatureMismatch",
+            InvalidColor(_) => "InvalidColor",
+            InvalidReplication(_) => "InvalidReplication",
+            OperationTimeout => "OperationTimeout",
+            NotAdmin => "NotAdmin",
+            OutOfBounds { .. } => "OutOfBounds",
+            InvalidRegion { .. } => "InvalidRegion",
+            RenderError(_) => "RenderError",
+            DimensionMismatch { .. } => "DimensionMismatch",
+            BatchTooLarge => "BatchTooLarge",
+            PixelBatchTooLarge => "PixelBatchTooLarge",
+            ReadBatchTooLarge => "ReadBatchTooLarge",
+            TagTooLong => "TagTooLong",
+            TimelapseTooLarge => "TimelapseTooLarge",
+        }
+    }
 }
-impl Error for VpError {}
 
 impl From<RedisError> for VpError {
     fn from(err: RedisError) -> Self {
@@ -32,6 +214,27 @@ impl From<QueryError> for VpError {
     }
 }
 
+impl From<FromRowError> for VpError {
+    fn from(err: FromRowError) -> Self {
+        Self::ScyllaRowErr(err)
+    }
+}
+
+impl From<NextRowError> for VpError {
+    fn from(err: NextRowError) -> Self {
+        match err {
+            NextRowError::QueryError(e) => Self::ScyllaQueryErr(e),
+            NextRowError::FromRowError(e) => Self::ScyllaRowErr(e),
+        }
+    }
+}
+
+impl From<ImageError> for VpError {
+    fn from(err: ImageError) -> Self {
+        Self::RenderError(err)
+    }
+}
+
 impl From<TryFromIntError> for VpError {
     fn from(err: TryFromIntError) -> Self {
         Self::ParseIntErr(err)
@@ -49,7 +252,11 @@ impl Display for VpError {
         use VpError::*;
         match self {
             InitCanvasErr => write!(f, "Unable to initialize canvas"),
+            InvalidKeyspace(keyspace) => write!(f, "[Invalid Keyspace]: {:?}", keyspace),
             RedisErr(e) => write!(f, "[Redis Error]: {}", e),
+            NoPalette(canvas_id) => {
+                write!(f, "[No Palette]: canvas {} is truecolor", canvas_id)
+            }
             ColorSizeMismatch => write!(
                 f,
                 "[Color Size Mismatch]: color size > 15. accepted range [0,15]"
@@ -57,13 +264,196 @@ impl Display for VpError {
             InvalidUser => write!(f, "[Invalid User]: Invalid User Id"),
             ScyllaQueryErr(e) => write!(f, "[Scylla Query Error]: {}", e),
             ScyllaTypeErr(e) => write!(f, "[Scylla Row Type Error]: {}", e),
+            ScyllaRowErr(e) => write!(f, "[Scylla Row Error]: {}", e),
             ScyllaSessionErr(e) => write!(f, "Unable to start New Scylla Session : {}", e),
             ParseIntErr(e) => write!(f, "[Error parsing Int]: {}", e),
             CanvasSizeMismatch => {
                 write!(f, "[Canvas Size Mismatch]: Enter (x,y) < Canvas Dimension")
             }
             NoPixelData => write!(f, "No pixel data found"),
+            PixelReadFailed { x, y, source } => {
+                write!(f, "[Pixel Read Failed]: ({},{}) : {}", x, y, source)
+            }
+            PixelWriteFailed { x, y, source } => {
+                write!(f, "[Pixel Write Failed]: ({},{}) : {}", x, y, source)
+            }
+            UserReadFailed { address, source } => {
+                write!(f, "[User Read Failed]: {} : {}", address, source)
+            }
+            DeltaTooOld => write!(
+                f,
+                "[Delta Too Old]: Gap to catch up is too old or too big, resync the canvas"
+            ),
+            RegionReserved { x, y } => {
+                write!(f, "[Region Reserved]: ({},{}) is reserved for admins", x, y)
+            }
+            ColorQuotaExceeded { color } => write!(
+                f,
+                "[Color Quota Exceeded]: color {} can't be placed again today",
+                color
+            ),
+            DailyQuotaExceeded { placed, limit } => write!(
+                f,
+                "[Daily Quota Exceeded]: {} of {} placements used today",
+                placed, limit
+            ),
+            PaletteMismatch {
+                canvas,
+                color,
+                colors,
+            } => write!(
+                f,
+                "[Palette Mismatch]: canvas {} stores color {}, the palette has {} colors",
+                canvas, color, colors
+            ),
+            UnknownPartition(part) => write!(f, "[Unknown Partition]: no canvas_part {}", part),
+            NotPixelOwner => write!(f, "[Not Pixel Owner]: only the owner can erase a pixel"),
+            InvalidAddressFormat(address) => write!(
+                f,
+                "[Invalid Address]: {} is not a 0x prefixed, checksummed EVM address",
+                address
+            ),
+            InvalidTimestamp(ts) => write!(
+                f,
+                "[Invalid Timestamp]: placed_at {} is in the future or not allowed",
+                ts
+            ),
+            InvalidNonce { expected } => {
+                write!(
+                    f,
+                    "[Invalid Nonce]: sign the placement with nonce {}",
+                    expected
+                )
+            }
+            ShuttingDown => write!(f, "[Shutting Down]: server is not accepting placements"),
+            ReadOnlyMode => write!(f, "[Read Only]: canvas is in maintenance, try again later"),
+            RateLimited { retry_after } => write!(
+                f,
+                "[Rate Limited]: too many placements, retry in {} seconds",
+                retry_after
+            ),
+            UnknownCanvas(id) => write!(f, "[Unknown Canvas]: no canvas with id {}", id),
+            UnknownTeam(team) => write!(f, "[Unknown Team]: {} is not a team of this event", team),
+            CooldownActive { remaining_secs } => write!(
+                f,
+                "[Cooldown Active]: wait {} seconds before placing",
+                remaining_secs
+            ),
+            InsufficientInk {
+                available,
+                required,
+            } => write!(
+                f,
+                "[Insufficient Ink]: {} ink left, the placement needs {}",
+                available, required
+            ),
+            BatchTooLarge => write!(
+                f,
+                "[Batch Too Large]: more than {} addresses",
+                super::scylla_models::MAX_USERS_BATCH
+            ),
+            PixelBatchTooLarge => write!(
+                f,
+                "[Pixel Batch Too Large]: more than {} pixels",
+                super::scylla_models::MAX_BATCH_PIXELS
+            ),
+            ReadBatchTooLarge => write!(
+                f,
+                "[Read Batch Too Large]: more than {} ops",
+                super::scylla_models::MAX_READ_OPS
+            ),
+            TagTooLong => write!(
+                f,
+                "[Tag Too Long]: tags are at most {} characters",
+                super::scylla_models::MAX_TAG_LEN
+            ),
+            TimelapseTooLarge => write!(
+                f,
+                "[Timelapse Too Large]: region area > {} cells or more than {} frames",
+                super::scylla_models::MAX_TIMELAPSE_AREA,
+                super::scylla_models::MAX_TIMELAPSE_FRAMES
+            ),
+            DimensionMismatch { width, height } => write!(
+                f,
+                "[Dimension Mismatch]: {}x{} image does not match the canvas",
+                width, height
+            ),
+            RenderError(e) => write!(f, "[Render Error]: {}", e),
+            OutOfBounds { x, y } => {
+                write!(f, "[Out Of Bounds]: ({},{}) is outside the canvas", x, y)
+            }
+            InvalidRegion { x0, y0, x1, y1 } => write!(
+                f,
+                "[Invalid Region]: ({},{}) is not the top left corner of ({},{})",
+                x0, y0, x1, y1
+            ),
+            NotAdmin => write!(f, "[Not Admin]: missing or invalid admin token"),
+            OperationTimeout => write!(f, "[Operation Timeout]: database did not respond in time"),
+            InvalidReplication(e) => write!(f, "[Invalid Replication Config]: {}", e),
+            InvalidColor(c) => write!(f, "[Invalid Color]: color {} not in palette", c),
+            SignatureMismatch => write!(
+                f,
+                "[Signature Mismatch]: signature not signed by the placing address"
+            ),
+            RegionTooLarge => write!(
+                f,
+                "[Region Too Large]: region area > {} cells",
+                super::scylla_models::MAX_REGION_AREA
+            ),
+        }
+    }
+}
+impl actix_web::ResponseError for VpError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            VpError::CooldownActive { .. } => StatusCode::FORBIDDEN,
+            VpError::InsufficientInk { .. } => StatusCode::FORBIDDEN,
+            VpError::SignatureMismatch => StatusCode::UNAUTHORIZED,
+            VpError::NotAdmin => StatusCode::UNAUTHORIZED,
+            VpError::OutOfBounds { .. } => StatusCode::BAD_REQUEST,
+            VpError::InvalidColor(_) => StatusCode::BAD_REQUEST,
+            VpError::InvalidRegion { .. } => StatusCode::BAD_REQUEST,
+            VpError::DimensionMismatch { .. } => StatusCode::BAD_REQUEST,
+            VpError::RegionTooLarge => StatusCode::BAD_REQUEST,
+            VpError::BatchTooLarge => StatusCode::BAD_REQUEST,
+            VpError::PixelBatchTooLarge => StatusCode::BAD_REQUEST,
+            VpError::ReadBatchTooLarge => StatusCode::BAD_REQUEST,
+            VpError::TagTooLong => StatusCode::BAD_REQUEST,
+            VpError::TimelapseTooLarge => StatusCode::BAD_REQUEST,
+            VpError::UnknownCanvas(_) => StatusCode::NOT_FOUND,
+            VpError::UnknownTeam(_) => StatusCode::BAD_REQUEST,
+            VpError::NoPalette(_) => StatusCode::BAD_REQUEST,
+            VpError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            VpError::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+            VpError::ReadOnlyMode => StatusCode::SERVICE_UNAVAILABLE,
+            VpError::OperationTimeout => StatusCode::GATEWAY_TIMEOUT,
+            VpError::InvalidNonce { .. } => StatusCode::CONFLICT,
+            VpError::InvalidTimestamp(_) => StatusCode::BAD_REQUEST,
+            VpError::InvalidAddressFormat(_) => StatusCode::BAD_REQUEST,
+            VpError::NotPixelOwner => StatusCode::FORBIDDEN,
+            VpError::UnknownPartition(_) => StatusCode::NOT_FOUND,
+            VpError::ColorQuotaExceeded { .. } => StatusCode::FORBIDDEN,
+            VpError::DailyQuotaExceeded { .. } => StatusCode::FORBIDDEN,
+            VpError::RegionReserved { .. } => StatusCode::FORBIDDEN,
+            VpError::DeltaTooOld => StatusCode::GONE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            // clients use remWait to show the countdown : )
+            VpError::CooldownActive { remaining_secs } => {
+                HttpResponse::Forbidden().json(WaitTime {
+                    rem_wait: *remaining_secs,
+                })
+            }
+            VpError::RateLimited { retry_after } => HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .insert_header(ContentType::plaintext())
+                .body(self.to_string()),
+            _ => HttpResponse::build(self.status_code())
+                .insert_header(ContentType::plaintext())
+                .body(self.to_string()),
         }
     }
 }
-impl actix_web::ResponseError for VpError {}
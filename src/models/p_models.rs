@@ -6,6 +6,8 @@ use actix_web::web;
 use actix_web_actors::ws;
 use serde::{Deserialize, Serialize};
 
+use super::scylla_models::Rect;
+
 #[derive(Serialize, Deserialize)]
 pub struct PixelLoc {
     pub x: u32,
@@ -18,7 +20,32 @@ pub struct PixelLoc {
 pub struct UpdatePixel {
     pub address: Option<String>,
     pub loc: PixelLoc,
-    pub color: u8,
+    // palette index, or 0xRRGGBB on truecolor canvases
+    pub color: u32,
+    // hex encoded personal_sign signature of the placement message
+    #[serde(default, skip_serializing)]
+    pub signature: Option<String>,
+    #[serde(default, skip_serializing)]
+    pub nonce: Option<u64>,
+    // client generated, a retry with the same key is a no-op
+    #[serde(default, skip_serializing)]
+    pub idempotency_key: Option<String>,
+    // unix seconds, for imports / replays by admin addresses. None is now
+    #[serde(default, skip_serializing)]
+    pub placed_at: Option<i64>,
+    // team the placement scores for, one of the configured teams
+    #[serde(default, skip_serializing)]
+    pub team: Option<String>,
+    // short note / emote shown when inspecting the pixel, signed with the placement
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+// coalesced websocket updates, {"pixels":[...]}
+#[derive(Message, Serialize)]
+#[rtype(result = "()")]
+pub struct PixelBatch {
+    pub pixels: Vec<UpdatePixel>,
 }
 
 #[derive(Serialize)]
@@ -28,25 +55,289 @@ pub struct CanvasResponse<'a> {
     pub canvas: &'a str,
 }
 
+// default : color returned for unpainted pixels instead of a 404
+#[derive(Deserialize)]
+pub struct PixelQuery {
+    pub default: Option<i32>,
+}
+
+// since : only pixels placed after this timestamp
+#[derive(Deserialize)]
+pub struct RegionQuery {
+    pub since: Option<i64>,
+    // include unpainted cells as the background color, ignored with since
+    pub empty: Option<bool>,
+}
+
+// optional region (inclusive corners), all four or none
+#[derive(Deserialize)]
+pub struct HistogramQuery {
+    pub x0: Option<u32>,
+    pub y0: Option<u32>,
+    pub x1: Option<u32>,
+    pub y1: Option<u32>,
+}
+
+// ts : unix seconds
+#[derive(Deserialize)]
+pub struct OwnerQuery {
+    pub ts: i64,
+}
+
+// raw : skip run length encoding of packed regions
+#[derive(Deserialize)]
+pub struct PackedQuery {
+    pub raw: Option<bool>,
+}
+
+// since : unix seconds of the last update the client saw
+#[derive(Deserialize)]
+pub struct DeltaQuery {
+    pub since: i64,
+}
+
+#[derive(Deserialize)]
+pub struct LimitQuery {
+    pub limit: Option<i32>,
+}
+
+// window : seconds back from now
+#[derive(Deserialize)]
+pub struct TopColorsQuery {
+    pub window: Option<i64>,
+    pub limit: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct HeatmapQuery {
+    pub bucket: Option<u32>,
+    // only placements at/after this timestamp
+    pub since: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadOnly {
+    pub read_only: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReshardingResponse {
+    pub needs_resharding: bool,
+}
+
+// a batch_read result, {"ok":...} or {"error":{"name":..,"message":..}}
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BatchReadItem<T> {
+    Ok(T),
+    Error { name: &'static str, message: String },
+}
+
+#[derive(Serialize)]
+pub struct ClearResponse {
+    pub cleared: u64,
+}
+
+// hex, a u64 doesn't survive JSON numbers in js
+#[derive(Serialize)]
+pub struct ChecksumResponse {
+    pub checksum: String,
+}
+
+#[derive(Serialize)]
+pub struct CountResponse {
+    pub painted: u64,
+}
+
+#[derive(Deserialize)]
+pub struct DumpQuery {
+    pub page_size: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct PngQuery {
+    pub scale: Option<u32>,
+    pub watermark: Option<String>,
+}
+
+// seconds of history per frame
+#[derive(Deserialize)]
+pub struct TimelapseQuery {
+    pub interval: Option<i64>,
+}
+
+// color the client saw on the pixel, unset : it saw it unpainted
+#[derive(Deserialize)]
+pub struct ExpectedQuery {
+    pub expected: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub struct AppliedResponse {
+    pub applied: bool,
+}
+
+#[derive(Deserialize)]
+pub struct HistoryPageQuery {
+    pub before: Option<i64>,
+    pub limit: Option<i32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryPage<T> {
+    pub items: Vec<T>,
+    // before of the next page, null on the last one
+    pub next_cursor: Option<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModifiedResponse {
+    pub last_modified: i64,
+}
+
+#[derive(Serialize)]
+pub struct TotalResponse {
+    pub total: i64,
+}
+
+#[derive(Serialize)]
+pub struct AreaResponse {
+    pub size: u32,
+    pub bounds: Rect,
+}
+
+#[derive(Serialize)]
+pub struct RgbResponse {
+    pub rgb: [u8; 3],
+}
+
+#[derive(Serialize)]
+pub struct FillResponse {
+    pub percentage: f64,
+}
+
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    pub address: String,
+}
+
+#[cfg(feature = "seed")]
+#[derive(Deserialize)]
+pub struct SeedQuery {
+    pub count: u64,
+    pub seed: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct ImportResponse {
+    pub imported: u64,
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct WaitTime {
     pub rem_wait: i64,
 }
 
+// Color palette, color index -> rgb
+#[derive(Clone)]
+pub struct Palette {
+    pub colors: Vec<[u8; 3]>,
+}
+// the redis canvas is a u4 bitfield, a palette index has to fit in a nibble
+pub const MAX_PALETTE_COLORS: usize = 16;
+
+impl Palette {
+    pub fn new(colors: Vec<[u8; 3]>) -> Self {
+        Self { colors }
+    }
+    // comma separated hex colors, eg: FFFFFF,E4E4E4,888888
+    pub fn parse(colors: &str) -> Option<Self> {
+        colors
+            .split(',')
+            .map(|c| {
+                let c = u32::from_str_radix(c.trim().trim_start_matches('#'), 16).ok()?;
+                let [_, r, g, b] = c.to_be_bytes();
+                Some([r, g, b])
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(Self::new)
+    }
+    // (index, "#RRGGBB") of every color, for clients
+    pub fn entries(&self) -> Vec<(i32, String)> {
+        (0..)
+            .zip(&self.colors)
+            .map(|(i, [r, g, b])| (i, format!("#{:02X}{:02X}{:02X}", r, g, b)))
+            .collect()
+    }
+    pub fn contains(&self, color: i32) -> bool {
+        usize::try_from(color).is_ok_and(|c| c < self.colors.len())
+    }
+    // 1 to MAX_PALETTE_COLORS colors, anything else can't be a canvas palette
+    pub fn fits_bitfield(&self) -> bool {
+        (1..=MAX_PALETTE_COLORS).contains(&self.colors.len())
+    }
+    // color of a request, u32 past i32 is never an index
+    pub fn contains_color(&self, color: u32) -> bool {
+        i32::try_from(color).is_ok_and(|c| self.contains(c))
+    }
+    // index of the closest palette color (squared rgb distance)
+    pub fn nearest(&self, rgb: [u8; 3]) -> Option<i32> {
+        let dist = |c: &[u8; 3]| {
+            c.iter()
+                .zip(rgb)
+                .map(|(a, b)| (i32::from(*a) - i32::from(b)).pow(2))
+                .sum::<i32>()
+        };
+        self.colors
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, c)| dist(c))
+            .and_then(|(i, _)| i32::try_from(i).ok())
+    }
+}
+impl Default for Palette {
+    // r/place 16 color palette : )
+    fn default() -> Self {
+        Self::new(vec![
+            [0xFF, 0xFF, 0xFF],
+            [0xE4, 0xE4, 0xE4],
+            [0x88, 0x88, 0x88],
+            [0x22, 0x22, 0x22],
+            [0xFF, 0xA7, 0xD1],
+            [0xE5, 0x00, 0x00],
+            [0xE5, 0x95, 0x00],
+            [0xA0, 0x6A, 0x42],
+            [0xE5, 0xD9, 0x00],
+            [0x94, 0xE0, 0x44],
+            [0x02, 0xBE, 0x01],
+            [0x00, 0xD3, 0xDD],
+            [0x00, 0x83, 0xC7],
+            [0x00, 0x00, 0xEA],
+            [0xCF, 0x6E, 0xE4],
+            [0x82, 0x00, 0x80],
+        ])
+    }
+}
+
 //AppState
 pub struct AppState<'a> {
     pub canvas_id: Cow<'a, str>,
     // dim*dim is the the real dimension of canvas
     pub canvas_dim: u32,
-    pub cooldown: usize,
+    // token for /admin routes, admin routes are disabled if None
+    pub admin_token: Option<String>,
 }
 impl<'a> AppState<'a> {
-    pub fn new(canvas_id: Cow<'a, str>, canvas_dim: u32, cooldown: usize) -> Self {
+    pub fn new(canvas_id: Cow<'a, str>, canvas_dim: u32, admin_token: Option<String>) -> Self {
         Self {
             canvas_id,
             canvas_dim,
-            cooldown,
+            admin_token,
         }
     }
 }
@@ -104,3 +395,34 @@ impl<'a> Actor for VpListener<'a> {
 impl<'a> Actor for VpSrv<'a> {
     type Context = actix::Context<Self>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palette_rejects_colors_outside_it() {
+        let palette = Palette::default();
+        let last = i32::try_from(palette.colors.len()).unwrap() - 1;
+        assert!(palette.contains(0));
+        assert!(palette.contains(last));
+        assert!(!palette.contains(last + 1));
+        assert!(!palette.contains(-1));
+        assert!(!palette.contains(999));
+        assert!(palette.contains_color(0));
+        assert!(!palette.contains_color(u32::MAX));
+    }
+
+    #[test]
+    fn palettes_fit_the_u4_canvas() {
+        assert!(Palette::default().fits_bitfield());
+        let colors = |n: usize| vec!["FFFFFF"; n].join(",");
+        assert!(Palette::parse(&colors(MAX_PALETTE_COLORS))
+            .unwrap()
+            .fits_bitfield());
+        assert!(!Palette::parse(&colors(MAX_PALETTE_COLORS + 1))
+            .unwrap()
+            .fits_bitfield());
+        assert!(!Palette::new(Vec::new()).fits_bitfield());
+    }
+}
@@ -1,37 +1,671 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroUsize;
+use std::ops::{Range, RangeInclusive};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
 use chrono::Utc;
+use futures::future::{join_all, try_join_all};
+use futures::{Future, Stream, StreamExt, TryFutureExt, TryStreamExt};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::png::PngEncoder;
+use image::{ColorType, Delay, Frame, ImageEncoder, ImageFormat, RgbaImage};
+use rand::Rng;
+use redis::aio::ConnectionManager;
+use scylla::batch::{Batch, BatchType};
+use scylla::frame::response::result::CqlValue;
+use scylla::frame::value::Counter;
 use scylla::prepared_statement::PreparedStatement;
+use scylla::query::Query;
+use scylla::statement::Consistency;
+use scylla::transport::errors::{DbError, QueryError};
 use scylla::transport::query_result::FirstRowTypedError;
-use scylla::{FromRow, FromUserType, IntoUserType, Session, SessionBuilder};
-use serde::Serialize;
+use scylla::transport::session::PoolSize;
+use scylla::transport::Compression;
+use scylla::{
+    ExecutionProfile, FromRow, FromUserType, IntoUserType, QueryResult, Session, SessionBuilder,
+};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+use tokio::sync::{broadcast, Notify};
+use tracing::field::Empty;
+use tracing::Span;
 
 use super::err_models::VpError;
-use super::p_models::UpdatePixel;
+use super::p_models::{Palette, PixelLoc, UpdatePixel};
+use crate::services::event_services::EventSink;
+use crate::services::render_services::{
+    downsample_majority, pack_rgb, stamp_text, unpack_rgb, upscale,
+};
+use crate::services::sig_services::normalize_address;
+
+// max cells fetched by a single region query : )
+pub const MAX_REGION_AREA: u64 = 65536;
+// concurrent column queries per region fetch
+const REGION_CONCURRENCY: usize = 16;
+// max addresses per get_users call, keeps the IN query small
+pub const MAX_USERS_BATCH: usize = 100;
+// max pixels per update_db_batch call, every one of them is checked and written at once
+pub const MAX_BATCH_PIXELS: usize = 1024;
+// max ops per batch_read call, each region op alone may fetch MAX_REGION_AREA cells
+pub const MAX_READ_OPS: usize = 16;
+// rows per page of the stats scans nobody waits on, keeps their memory low
+const STATS_PAGE_SIZE: i32 = 500;
+// max chars of a placement tag
+pub const MAX_TAG_LEN: usize = 32;
+// rows of the global placement counter
+const GLOBAL_STAT_SHARDS: i32 = 8;
+// first byte of a packed region : cells as is / run length encoded
+const PACKED_RAW: u8 = 0;
+const PACKED_RLE: u8 = 1;
+// color of the history entry left by an erase, never a palette index
+const ERASED_COLOR: i32 = -1;
+// recent_feed partitions span this many seconds, feed rows expire after two of them
+const RECENT_FEED_BUCKET_SECS: i64 = 3600;
+// placements returned by delta_since at most, bigger gaps need a full resync
+const MAX_DELTA_LEN: usize = 10_000;
+// fill_percentage is recounted at most once per this many seconds
+const FILL_CACHE_SECS: u64 = 30;
+// max width / height of a rendered png, bigger scales are lowered to fit
+const MAX_RENDER_DIM: u32 = 4096;
+// seconds a placed_at may be ahead of this server's clock
+const MAX_CLOCK_SKEW_SECS: i64 = 5;
+// placements only bump canvas_meta once the stored timestamp is this many seconds old
+const META_RESOLUTION_SECS: i64 = 1;
+// schema changes of existing deployments, in order : schema_version stores how many ran.
+// append only, a shipped migration is never edited or removed. {ks} is the keyspace.
+// init_table creates new tables with every column already, and these predate the runner,
+// so a column / field that exists (Invalid) counts as applied
+const MIGRATIONS: &[&str] = &[
+    "ALTER TABLE {ks}.player ADD streak int",
+    "ALTER TABLE {ks}.player ADD nonce bigint",
+    "ALTER TABLE {ks}.recent_feed ADD canvas_id text",
+    "ALTER TABLE {ks}.player ADD team text",
+    "ALTER TABLE {ks}.player ADD ink bigint",
+    "ALTER TABLE {ks}.player ADD ink_updated_at timestamp",
+    "ALTER TYPE {ks}.pixel_data ADD tag text",
+];
+// max cells / frames of a timelapse gif, every frame is quantized on its own
+pub const MAX_TIMELAPSE_AREA: u64 = 16384;
+pub const MAX_TIMELAPSE_FRAMES: i64 = 300;
+// display time of one timelapse frame
+const TIMELAPSE_FRAME_MS: u32 = 100;
+// stored pixels read per partition by the palette check
+const PALETTE_SAMPLE_ROWS: i32 = 100;
+// statements per batch of a png import or a stamp partition
+const IMPORT_BATCH_SIZE: usize = 256;
+
+// keyspace replication
+pub enum ReplicationStrategy {
+    Simple,
+    NetworkTopology,
+}
+pub struct ReplicationConfig {
+    pub strategy: ReplicationStrategy,
+    pub replication_factor: u32,
+    // per datacenter replication factor, only for NetworkTopology
+    pub datacenters: Vec<(String, u32)>,
+}
+impl Default for ReplicationConfig {
+    // single node setup : )
+    fn default() -> Self {
+        Self {
+            strategy: ReplicationStrategy::NetworkTopology,
+            replication_factor: 1,
+            datacenters: Vec::new(),
+        }
+    }
+}
+impl ReplicationConfig {
+    // CQL replication map for CREATE KEYSPACE
+    fn to_cql(&self) -> Result<String, VpError> {
+        let class = match self.strategy {
+            ReplicationStrategy::Simple => "SimpleStrategy",
+            ReplicationStrategy::NetworkTopology => "NetworkTopologyStrategy",
+        };
+        let mut cql = format!(
+            "{{'class' : '{}', 'replication_factor' : {}",
+            class, self.replication_factor
+        );
+        if !self.datacenters.is_empty() {
+            if let ReplicationStrategy::Simple = self.strategy {
+                return Err(VpError::InvalidReplication(
+                    "datacenters need NetworkTopologyStrategy".to_string(),
+                ));
+            }
+        }
+        for (dc, rf) in &self.datacenters {
+            // dc names are interpolated into CQL, allow only [A-Za-z0-9_-]
+            let valid = !dc.is_empty()
+                && dc
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if !valid {
+                return Err(VpError::InvalidReplication(format!(
+                    "invalid datacenter name {:?}",
+                    dc
+                )));
+            }
+            cql.push_str(&format!(", '{}' : {}", dc, rf));
+        }
+        cql.push('}');
+        Ok(cql)
+    }
+}
+
+// cooldown growth of rapid repeat placers : base * factor^streak, capped at max_secs
+// each placement within reset_after_secs of the previous one bumps the streak
+#[derive(Clone, Copy)]
+pub struct CooldownScaling {
+    pub factor: f64,
+    pub max_secs: i64,
+    pub reset_after_secs: i64,
+}
+
+// ink budget, replaces the cooldown : every placement costs cost ink,
+// one ink regenerates every refill_secs up to max. new users start full
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InkBudget {
+    pub max: i64,
+    pub cost: i64,
+    pub refill_secs: i64,
+}
+impl InkBudget {
+    // (ink, as of) of a balance stored at updated_at, refilled up to now
+    // progress towards the next unit is kept, a full budget restarts the clock
+    fn refill(&self, ink: i64, updated_at: i64, now: i64) -> (i64, i64) {
+        let refill_secs = self.refill_secs.max(1);
+        let units = (now - updated_at).max(0) / refill_secs;
+        let ink = ink.saturating_add(units);
+        if ink >= self.max {
+            (self.max, now)
+        } else {
+            (ink, updated_at + units * refill_secs)
+        }
+    }
+}
+
+// retry policy for transient scylla errors (timeouts, unavailable, overloaded)
+#[derive(Clone)]
+pub struct RetryConfig {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    // random extra delay in [0, jitter) added to each backoff
+    pub jitter: Duration,
+}
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(50),
+            jitter: Duration::from_millis(25),
+        }
+    }
+}
+impl RetryConfig {
+    fn is_transient(err: &VpError) -> bool {
+        matches!(
+            err,
+            VpError::ScyllaQueryErr(
+                QueryError::TimeoutError
+                    | QueryError::RequestTimeout(_)
+                    | QueryError::DbError(
+                        DbError::ReadTimeout { .. }
+                            | DbError::WriteTimeout { .. }
+                            | DbError::Unavailable { .. }
+                            | DbError::Overloaded,
+                        _
+                    )
+            )
+        )
+    }
+    // only run idempotent statements through this : )
+    async fn run<T, F, Fut>(&self, mut op: F) -> Result<T, VpError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, VpError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Err(e) if attempt < self.max_attempts && Self::is_transient(&e) => {
+                    let backoff = self.base_delay * 2u32.pow(attempt - 1)
+                        + self.jitter.mul_f64(rand::random::<f64>());
+                    log::warn!(
+                        "Transient scylla error (attempt {}/{}), retrying in {:?} : {}",
+                        attempt,
+                        self.max_attempts,
+                        backoff,
+                        e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+// session connect attempts in try_build, scylla may still be booting (docker compose)
+#[derive(Clone)]
+pub struct ConnectRetry {
+    pub max_attempts: u32,
+    pub delay: Duration,
+}
+impl Default for ConnectRetry {
+    fn default() -> Self {
+        Self {
+            max_attempts: 10,
+            delay: Duration::from_secs(3),
+        }
+    }
+}
+
+// session tuning, None keeps the driver default
+#[derive(Clone)]
+pub struct SessionOptions {
+    pub connection_timeout: Option<Duration>,
+    pub request_timeout: Option<Duration>,
+    pub compression: Option<Compression>,
+    // connections per shard (scylla) or per host, see DEFAULT_CONNECTIONS_PER_SHARD
+    pub pool_size: PoolSize,
+    // connect through the regular port only, for NATs / proxies that hide the shard aware port
+    pub disallow_shard_aware_port: bool,
+}
+// placements are many tiny writes, a second connection per shard keeps a slow
+// response from stalling the writes queued behind it on the same connection : )
+const DEFAULT_CONNECTIONS_PER_SHARD: usize = 2;
+impl Default for SessionOptions {
+    fn default() -> Self {
+        Self {
+            connection_timeout: None,
+            request_timeout: None,
+            compression: None,
+            pool_size: PoolSize::PerShard(
+                NonZeroUsize::new(DEFAULT_CONNECTIONS_PER_SHARD).unwrap_or(NonZeroUsize::MIN),
+            ),
+            disallow_shard_aware_port: false,
+        }
+    }
+}
+
+// consistency level of reads / writes
+#[derive(Clone, Copy)]
+pub enum DbConsistency {
+    One,
+    LocalOne,
+    Quorum,
+    LocalQuorum,
+    All,
+}
+impl DbConsistency {
+    pub fn parse(level: &str) -> Option<Self> {
+        match level.to_ascii_uppercase().as_str() {
+            "ONE" => Some(Self::One),
+            "LOCAL_ONE" => Some(Self::LocalOne),
+            "QUORUM" => Some(Self::Quorum),
+            "LOCAL_QUORUM" => Some(Self::LocalQuorum),
+            "ALL" => Some(Self::All),
+            _ => None,
+        }
+    }
+}
+impl From<DbConsistency> for Consistency {
+    fn from(level: DbConsistency) -> Self {
+        match level {
+            DbConsistency::One => Consistency::One,
+            DbConsistency::LocalOne => Consistency::LocalOne,
+            DbConsistency::Quorum => Consistency::Quorum,
+            DbConsistency::LocalQuorum => Consistency::LocalQuorum,
+            DbConsistency::All => Consistency::All,
+        }
+    }
+}
+
+// what try_build does with stored colors outside the configured palette
+#[derive(Clone, Copy)]
+pub enum PaletteCheck {
+    Warn,
+    Error,
+}
+impl PaletteCheck {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode.to_ascii_lowercase().as_str() {
+            "warn" => Some(Self::Warn),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
 
 //ScyllaBuilder
 pub struct ScyllaBuilder {
-    session: Session,
-    dim_mid: u32,
+    scylla_url: String,
+    connect_retry: ConnectRetry,
+    session_options: SessionOptions,
+    canvas_dim: u32,
+    shards: u32,
+    replication: String,
+    cooldown: i64,
+    cooldown_scaling: Option<CooldownScaling>,
+    ink: Option<InkBudget>,
+    free_placements: i64,
+    daily_quota: i64,
+    color_quotas: HashMap<i32, i64>,
+    reserved_regions: Vec<Rect>,
+    region_cooldowns: Vec<(Rect, i64)>,
+    color_mode: ColorMode,
+    pixel_cache: Option<(redis::Client, usize)>,
+    health_timeout: Duration,
+    op_timeout: Duration,
+    retry: RetryConfig,
+    // None -> session default
+    read_consistency: Option<DbConsistency>,
+    write_consistency: Option<DbConsistency>,
+    page_size: i32,
+    palette_check: Option<PaletteCheck>,
+    pixel_ttl_secs: Option<u32>,
+    metrics: bool,
+    render_background: [u8; 3],
+    background_color: i32,
+    update_capacity: usize,
+    reshard_threshold: i64,
+    admin_addresses: HashSet<String>,
+    teams: HashSet<String>,
+    canvas_id: Option<String>,
+    canvases: Vec<(String, CanvasConfig)>,
+    idempotency_window: Duration,
+    event_sink: Option<Arc<dyn EventSink>>,
+    keyspace: String,
 }
 impl ScyllaBuilder {
-    pub async fn try_init(scylla_url: &str, canvas_dim: u32) -> Result<Self, VpError> {
-        let session = SessionBuilder::new().known_node(scylla_url).build().await?;
-        let dim_mid = canvas_dim / 2;
-        Ok(Self { session, dim_mid })
+    pub async fn try_init(
+        scylla_url: &str,
+        canvas_dim: u32,
+        replication: ReplicationConfig,
+        connect_retry: ConnectRetry,
+    ) -> Result<Self, VpError> {
+        let replication = replication.to_cql()?;
+        // session is opened by try_build, so with_options can still tune it : )
+        Ok(Self {
+            scylla_url: scylla_url.to_string(),
+            connect_retry,
+            session_options: SessionOptions::default(),
+            canvas_dim,
+            shards: 2,
+            replication,
+            cooldown: 60,
+            cooldown_scaling: None,
+            ink: None,
+            free_placements: 0,
+            daily_quota: 0,
+            color_quotas: HashMap::new(),
+            reserved_regions: Vec::new(),
+            region_cooldowns: Vec::new(),
+            color_mode: ColorMode::default(),
+            pixel_cache: None,
+            health_timeout: Duration::from_secs(2),
+            op_timeout: Duration::from_secs(10),
+            retry: RetryConfig::default(),
+            read_consistency: None,
+            write_consistency: None,
+            page_size: 5000,
+            palette_check: None,
+            pixel_ttl_secs: None,
+            metrics: false,
+            render_background: [0xFF, 0xFF, 0xFF],
+            background_color: 0,
+            update_capacity: 1024,
+            reshard_threshold: 1_000_000,
+            admin_addresses: HashSet::new(),
+            teams: HashSet::new(),
+            canvas_id: None,
+            canvases: Vec::new(),
+            idempotency_window: Duration::from_secs(300),
+            event_sink: None,
+            keyspace: "opbnbplace".to_string(),
+        })
+    }
+    // cooldown in seconds between two placements of a user
+    pub fn with_cooldown(mut self, cooldown: i64) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+    // the first `count` placements of a wallet skip the cooldown, 0 (default) disables it
+    pub fn with_free_placements(mut self, count: u32) -> Self {
+        self.free_placements = i64::from(count);
+        self
+    }
+    // max placements per utc day of a wallet, 0 (default) disables it. admins are exempt
+    pub fn with_daily_quota(mut self, limit: u32) -> Self {
+        self.daily_quota = i64::from(limit);
+        self
+    }
+    // max placements per utc day of a color, over the whole site. colors without one are unlimited
+    pub fn with_color_quotas(mut self, quotas: impl IntoIterator<Item = (i32, u32)>) -> Self {
+        self.color_quotas = quotas
+            .into_iter()
+            .map(|(color, quota)| (color, i64::from(quota)))
+            .collect();
+        self
+    }
+    // rectangles of the default canvas only admin addresses may paint, they can overlap
+    pub fn with_reserved_regions(mut self, regions: Vec<Rect>) -> Self {
+        self.reserved_regions = regions;
+        self
+    }
+    // (region, cooldown secs) of the default canvas, placements elsewhere use with_cooldown
+    // the smallest region wins where they overlap
+    pub fn with_region_cooldowns(mut self, cooldowns: Vec<(Rect, i64)>) -> Self {
+        self.region_cooldowns = cooldowns;
+        self
+    }
+    // scale the cooldown of users placing back to back, unset keeps it flat
+    pub fn with_cooldown_scaling(mut self, scaling: CooldownScaling) -> Self {
+        self.cooldown_scaling = Some(scaling);
+        self
+    }
+    // rate limit placements by ink instead of the cooldown (and its scaling / free placements)
+    pub fn with_ink_budget(mut self, ink: InkBudget) -> Self {
+        self.ink = Some(ink);
+        self
+    }
+    // canvas is split into a shards x shards grid of partitions
+    // default 2 -> the four quadrants : )
+    pub fn with_shards(mut self, shards: u32) -> Self {
+        self.shards = shards.max(1);
+        self
     }
-    async fn init_table(&self) -> Result<(), VpError> {
+    // read-through redis cache for get_pixel, ttl 0 disables it
+    pub fn with_pixel_cache(mut self, redis: redis::Client, ttl_secs: usize) -> Self {
+        self.pixel_cache = (ttl_secs > 0).then_some((redis, ttl_secs));
+        self
+    }
+    // max time a health check may take
+    pub fn with_health_timeout(mut self, timeout: Duration) -> Self {
+        self.health_timeout = timeout;
+        self
+    }
+    // deadline of one instrumented operation (get_user, get_pixel, update_db ...), retries included
+    pub fn with_op_timeout(mut self, timeout: Duration) -> Self {
+        self.op_timeout = timeout;
+        self
+    }
+    // retries of transient errors in update_db and get_pixel
+    pub fn with_retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+    // consistency of pixel/user reads, eg: ONE for best effort reads
+    pub fn with_read_consistency(mut self, level: DbConsistency) -> Self {
+        self.read_consistency = Some(level);
+        self
+    }
+    // consistency of placement writes, eg: LOCAL_QUORUM during events
+    pub fn with_write_consistency(mut self, level: DbConsistency) -> Self {
+        self.write_consistency = Some(level);
+        self
+    }
+    // samples stored pixels of each palette canvas on build, so a palette that differs from the
+    // one that painted the canvas (config drift) is caught on deploy. off by default
+    pub fn with_palette_check(mut self, check: PaletteCheck) -> Self {
+        self.palette_check = Some(check);
+        self
+    }
+    // rows fetched per page by full canvas scans
+    pub fn with_page_size(mut self, page_size: i32) -> Self {
+        self.page_size = page_size;
+        self
+    }
+    // ephemeral canvases, pixels expire ttl seconds after placement
+    // only the canvas write is TTL'd, player rows (cooldown) and history persist
+    pub fn with_pixel_ttl(mut self, ttl_secs: u32) -> Self {
+        self.pixel_ttl_secs = (ttl_secs > 0).then_some(ttl_secs);
+        self
+    }
+    // record latency/outcome of db ops through the `metrics` facade
+    // plug any exporter by installing a recorder in main
+    pub fn with_metrics(mut self, enabled: bool) -> Self {
+        self.metrics = enabled;
+        self
+    }
+    // rgb of unpainted cells in rendered images, white by default
+    pub fn with_render_background(mut self, rgb: [u8; 3]) -> Self {
+        self.render_background = rgb;
+        self
+    }
+    // palette index read APIs report for unpainted cells, 0 (white) by default
+    // must be in the palette of every canvas
+    pub fn with_background_color(mut self, color: i32) -> Self {
+        self.background_color = color;
+        self
+    }
+    // pixel updates buffered per subscriber
+    // a subscriber more than capacity updates behind lags and drops the oldest ones
+    pub fn with_update_capacity(mut self, capacity: usize) -> Self {
+        self.update_capacity = capacity.max(1);
+        self
+    }
+    // history rows of a canvas_part past which needs_resharding is raised
+    pub fn with_reshard_threshold(mut self, cells: u64) -> Self {
+        self.reshard_threshold = i64::try_from(cells).unwrap_or(i64::MAX);
+        self
+    }
+    // wallets that place without cooldown (event operators, seed bot)
+    // stored lowercase, eth addresses are compared case-insensitively : )
+    pub fn with_admin_addresses(mut self, addresses: impl IntoIterator<Item = String>) -> Self {
+        self.admin_addresses = addresses
+            .into_iter()
+            .map(|address| address.to_lowercase())
+            .collect();
+        self
+    }
+    // teams placements may score for, none configured rejects every team
+    pub fn with_teams(mut self, teams: impl IntoIterator<Item = String>) -> Self {
+        self.teams = teams.into_iter().collect();
+        self
+    }
+    // connection / request timeouts and frame compression of the session
+    pub fn with_options(mut self, options: SessionOptions) -> Self {
+        self.session_options = options;
+        self
+    }
+    // keyspace every table lives in, so test and prod instances can share a cluster
+    pub fn with_keyspace(mut self, keyspace: &str) -> Self {
+        self.keyspace = keyspace.to_string();
+        self
+    }
+    // prefix of the partition names ({canvas_id}_p{n}) and pixel cache keys,
+    // so several canvases can share a keyspace. unset keeps the legacy v_part{n} names
+    pub fn with_canvas_id(mut self, canvas_id: &str) -> Self {
+        self.canvas_id = Some(canvas_id.to_string());
+        self
+    }
+    // extra canvas served next to the default one, its partitions are {canvas_id}_p{n}
+    // cooldown, stats and player history stay shared by every canvas : )
+    pub fn with_canvas(mut self, canvas_id: &str, config: CanvasConfig) -> Self {
+        self.canvases.push((canvas_id.to_string(), config));
+        self
+    }
+    // how long an idempotency key is remembered, 5 minutes by default
+    // a retry after the window is a new placement (and hits the cooldown)
+    pub fn with_idempotency_window(mut self, window: Duration) -> Self {
+        self.idempotency_window = window.max(Duration::from_secs(1));
+        self
+    }
+    // every placement is published to the sink once written, failures are only logged
+    pub fn with_event_sink(mut self, sink: Arc<dyn EventSink>) -> Self {
+        self.event_sink = Some(sink);
+        self
+    }
+    // allowed pixel colors
+    pub fn with_palette(mut self, palette: Palette) -> Self {
+        self.color_mode = ColorMode::Palette(palette);
+        self
+    }
+    async fn connect(&self) -> Result<Session, VpError> {
+        let options = &self.session_options;
+        let mut builder = SessionBuilder::new()
+            .known_node(&self.scylla_url)
+            .compression(options.compression)
+            .pool_size(options.pool_size)
+            .disallow_shard_aware_port(options.disallow_shard_aware_port);
+        if let Some(timeout) = options.connection_timeout {
+            builder = builder.connection_timeout(timeout);
+        }
+        if let Some(timeout) = options.request_timeout {
+            let profile = ExecutionProfile::builder()
+                .request_timeout(Some(timeout))
+                .build();
+            builder = builder.default_execution_profile_handle(profile.into_handle());
+        }
+        let mut attempt = 1;
+        loop {
+            match builder.build().await {
+                Ok(session) => return Ok(session),
+                Err(e) if attempt < self.connect_retry.max_attempts => {
+                    log::warn!(
+                        "Unable to connect to scylla (attempt {}/{}), retrying in {:?} : {}",
+                        attempt,
+                        self.connect_retry.max_attempts,
+                        self.connect_retry.delay,
+                        e
+                    );
+                    tokio::time::sleep(self.connect_retry.delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+    async fn init_table(&self, session: &Session) -> Result<(), VpError> {
+        let ks = &self.keyspace;
         //Store Pixel Update of Each User
         //->used to check cooldown
-        self.session.query("CREATE KEYSPACE IF NOT EXISTS opbnbplace WITH REPLICATION = {'class' : 'NetworkTopologyStrategy', 'replication_factor' : 1}", &[]).await?;
+        session
+            .query(
+                format!(
+                    "CREATE KEYSPACE IF NOT EXISTS {ks} WITH REPLICATION = {}",
+                    self.replication
+                ),
+                &[],
+            )
+            .await?;
         //table to store User's last pixel placement
-        self.session
-        .query("CREATE TABLE IF NOT EXISTS opbnbplace.player (address text,x int,y int,color int,last_placed timestamp,PRIMARY KEY (address))", &[])
+        session
+        .query(format!("CREATE TABLE IF NOT EXISTS {ks}.player (address text,x int,y int,color int,last_placed timestamp,streak int,nonce bigint,team text,ink bigint,ink_updated_at timestamp,PRIMARY KEY (address))"), &[])
         .await?;
 
         //Store All Pixel data
         // UDT to store pixel_data
-        self.session.query("CREATE TYPE IF NOT EXISTS opbnbplace.pixel_data (address text,color int,last_placed timestamp)",&[]).await?;
+        // pixels written before tags read the missing field as null
+        session.query(format!("CREATE TYPE IF NOT EXISTS {ks}.pixel_data (address text,color int,last_placed timestamp,tag text)"),&[]).await?;
         //table to store all pixel update data in canvas
-        // Divide the canvas into 4 parts
+        // Divide the canvas into shards x shards parts, by default 4 parts
         //       ---------------
         //       |      |      |
         //       |   1  |  2   |
@@ -39,128 +673,4527 @@ impl ScyllaBuilder {
         //       |   3  |  4   |
         //       |      |      |
         //       --------------
+        // part index = (x / shard_size) * shards + y / shard_size
         // each part is row with pixel details as column of the form (x,y):pixel_data
         // where pixel_data is UDT defined above : ) .
-        self.session.query("CREATE TABLE IF NOT EXISTS opbnbplace.canvas ( canvas_part text,x int ,y int,data frozen<pixel_data>,PRIMARY KEY (canvas_part,x,y))",&[]).await?;
+        session.query(format!("CREATE TABLE IF NOT EXISTS {ks}.canvas ( canvas_part text,x int ,y int,data frozen<pixel_data>,PRIMARY KEY (canvas_part,x,y))"),&[]).await?;
+        //table to store every placement of a pixel, same partitions as canvas
+        // newest placement first : )
+        session.query(format!("CREATE TABLE IF NOT EXISTS {ks}.canvas_history ( canvas_part text,x int ,y int,last_placed timestamp,data frozen<pixel_data>,PRIMARY KEY (canvas_part,x,y,last_placed)) WITH CLUSTERING ORDER BY (x ASC, y ASC, last_placed DESC)"),&[]).await?;
+        //placement count per user for the leaderboard
+        session.query(format!("CREATE TABLE IF NOT EXISTS {ks}.player_stats (address text,total_placed counter,PRIMARY KEY (address))"),&[]).await?;
+        //placement count per team for the event scoreboard
+        session.query(format!("CREATE TABLE IF NOT EXISTS {ks}.team_stats (team text,total_placed counter,PRIMARY KEY (team))"),&[]).await?;
+        //every placement of a user, newest first
+        session.query(format!("CREATE TABLE IF NOT EXISTS {ks}.player_history (address text,last_placed timestamp,x int,y int,color int,PRIMARY KEY (address,last_placed,x,y)) WITH CLUSTERING ORDER BY (last_placed DESC, x ASC, y ASC)"),&[]).await?;
+        //every cell a user has painted, so their pixels can be found without a canvas scan
+        session.query(format!("CREATE TABLE IF NOT EXISTS {ks}.user_pixels (address text,canvas_part text,x int,y int,PRIMARY KEY (address,canvas_part,x,y))"),&[]).await?;
+        //idempotency keys of recent placements, rows expire with the dedup window
+        session.query(format!("CREATE TABLE IF NOT EXISTS {ks}.placement_keys (address text,idempotency_key text,PRIMARY KEY (address,idempotency_key))"),&[]).await?;
+        //last change of each canvas, for conditional snapshot responses
+        session.query(format!("CREATE TABLE IF NOT EXISTS {ks}.canvas_meta (canvas_id text,last_update timestamp,PRIMARY KEY (canvas_id))"),&[]).await?;
+        //latest placements of the whole site, one partition per RECENT_FEED_BUCKET_SECS, newest first
+        // rows without a canvas_id (feeds from before deltas) belong to no canvas
+        session.query(format!("CREATE TABLE IF NOT EXISTS {ks}.recent_feed (bucket bigint,last_placed timestamp,address text,x int,y int,color int,canvas_id text,PRIMARY KEY (bucket,last_placed,address,x,y)) WITH CLUSTERING ORDER BY (last_placed DESC, address ASC, x ASC, y ASC)"),&[]).await?;
+        // counters can't expire, rows of past days just stop being read (one per color and day)
+        session.query(format!("CREATE TABLE IF NOT EXISTS {ks}.color_quota (day bigint,color int,placed counter,PRIMARY KEY ((day,color)))"),&[]).await?;
+        // placements of a wallet on one utc day, rows expire at the next midnight
+        session.query(format!("CREATE TABLE IF NOT EXISTS {ks}.daily_quota (address text,day bigint,placed bigint,PRIMARY KEY ((address,day)))"),&[]).await?;
+        // history rows written per canvas_part, the estimate behind needs_resharding
+        session.query(format!("CREATE TABLE IF NOT EXISTS {ks}.partition_cells (canvas_part text,cells counter,PRIMARY KEY (canvas_part))"),&[]).await?;
+        //total placements, split over GLOBAL_STAT_SHARDS rows
+        session.query(format!("CREATE TABLE IF NOT EXISTS {ks}.global_stats (shard int,total_placed counter,PRIMARY KEY (shard))"),&[]).await?;
+        //MIGRATIONS applied so far, a single row
+        session.query(format!("CREATE TABLE IF NOT EXISTS {ks}.schema_version (id int,version int,PRIMARY KEY (id))"),&[]).await?;
+        self.migrate(session).await
+    }
+    // runs the MIGRATIONS newer than schema_version, the version is stored after each one
+    // so an interrupted start resumes. instances starting together may both run one,
+    // the second gets Invalid : ) a second run with nothing new only reads the version
+    async fn migrate(&self, session: &Session) -> Result<(), VpError> {
+        let ks = &self.keyspace;
+        let rows = session
+            .query(
+                format!("SELECT version FROM {ks}.schema_version WHERE id = 0"),
+                &[],
+            )
+            .await?;
+        let version = match rows.first_row_typed::<(i32,)>() {
+            Ok((version,)) => usize::try_from(version)?,
+            Err(FirstRowTypedError::RowsEmpty) => 0,
+            Err(e) => return Err(VpError::ScyllaTypeErr(e)),
+        };
+        if version > MIGRATIONS.len() {
+            log::warn!(
+                "Schema version {} is newer than this build ({}), skipping migrations",
+                version,
+                MIGRATIONS.len()
+            );
+            return Ok(());
+        }
+        for (applied, migration) in pending_migrations(version) {
+            match session.query(migration.replace("{ks}", ks), &[]).await {
+                Ok(_) => {}
+                Err(e) if already_applied(&e) => {}
+                Err(e) => return Err(e.into()),
+            }
+            session
+                .query(
+                    format!("INSERT INTO {ks}.schema_version (id, version) VALUES (0, ?)"),
+                    (i32::try_from(applied)?,),
+                )
+                .await?;
+        }
         Ok(())
     }
 
+    // ttl is a builder time constant so it's part of the statement, not a bind value
+    fn pixel_ttl_clause(&self) -> String {
+        self.pixel_ttl_secs
+            .map_or_else(String::new, |ttl| format!(" USING TTL {}", ttl))
+    }
+    // canvas cell insert, condition is empty or an LWT clause like " IF NOT EXISTS"
+    fn insert_pixel_cql(&self, condition: &str) -> String {
+        let ks = &self.keyspace;
+        let ttl = self.pixel_ttl_clause();
+        format!(
+            "INSERT INTO {ks}.canvas (canvas_part,x,y,data) VALUES (?, ?, ?, ?){condition}{ttl}"
+        )
+    }
+    // player row insert, never TTL'd so cooldowns and nonces outlive expired pixels
+    fn insert_player_cql(&self, condition: &str) -> String {
+        let ks = &self.keyspace;
+        format!("INSERT INTO {ks}.player (address, x, y, color, last_placed, streak, nonce) VALUES (?, ?, ?, ?, ?, ?, ?){condition}")
+    }
+
+    #[tracing::instrument(
+        name = "try_build",
+        skip_all,
+        fields(canvas_dim = self.canvas_dim, shards = self.shards),
+        err(Display)
+    )]
     pub async fn try_build(self) -> Result<ScyllaManager, VpError> {
-        self.init_table().await?;
-        let insert_user=self.session.prepare("INSERT INTO opbnbplace.player (address, x, y, color, last_placed) VALUES (?, ?, ?, ?, ?)").await?;
-        let get_user = self
-            .session
-            .prepare(
-                "SELECT address, x, y, color, last_placed FROM opbnbplace.player WHERE address = ?",
-            )
+        let ks = &self.keyspace;
+        // it's interpolated into every statement, so only plain cql identifiers
+        if !is_keyspace_name(ks) {
+            return Err(VpError::InvalidKeyspace(ks.to_string()));
+        }
+        let background = self.background_color;
+        // packed regions store it as a color byte too : )
+        if u8::try_from(background).is_err()
+            || !self.color_mode.contains(background)
+            || self
+                .canvases
+                .iter()
+                .any(|(_, c)| !c.color_mode.contains(background))
+        {
+            return Err(VpError::InvalidColor(background));
+        }
+        let session = self.connect().await?;
+        self.init_table(&session).await?;
+        let pixel_ttl = self.pixel_ttl_clause();
+        // hot path statements are prepared concurrently, the first failure is returned : )
+        let (mut insert_user, mut get_user, mut insert_pixel, mut get_pixel) = tokio::try_join!(
+            session.prepare(self.insert_player_cql("")),
+            session.prepare(
+                format!("SELECT address, x, y, color, last_placed, streak, nonce, ink, ink_updated_at FROM {ks}.player WHERE address = ?"),
+            ),
+            session.prepare(self.insert_pixel_cql("")),
+            session.prepare(format!("SELECT data FROM {ks}.canvas WHERE canvas_part = ? AND x=? AND y=?")),
+        )?;
+        // LWT compare and set of a canvas cell, see update_db_if
+        let insert_pixel_if_empty = session
+            .prepare(self.insert_pixel_cql(" IF NOT EXISTS"))
             .await?;
-        let insert_pixel = self
-            .session
-            .prepare("INSERT INTO opbnbplace.canvas (canvas_part,x,y,data) VALUES (?, ?, ?, ?)")
+        let claim_player = session
+            .prepare(format!("UPDATE {ks}.player SET x = ?, y = ?, color = ?, last_placed = ?, streak = ?, nonce = ? WHERE address = ? IF last_placed <= ?"))
             .await?;
-        let get_pixel = self
-            .session
-            .prepare("SELECT data FROM opbnbplace.canvas WHERE canvas_part = ? AND x=? AND y=?")
+        let claim_new_player = session
+            .prepare(self.insert_player_cql(" IF NOT EXISTS"))
+            .await?;
+        let update_pixel_if = session
+            .prepare(format!(
+                "UPDATE {ks}.canvas{} SET data = ? WHERE canvas_part = ? AND x = ? AND y = ? IF data = ?",
+                pixel_ttl
+            ))
+            .await?;
+        let mut insert_history = session
+            .prepare(format!("INSERT INTO {ks}.canvas_history (canvas_part,x,y,last_placed,data) VALUES (?, ?, ?, ?, ?)"))
+            .await?;
+        // history is clustered newest first, so the first row <= ts is the one active at ts
+        let mut get_owner_at = session
+            .prepare(format!("SELECT data FROM {ks}.canvas_history WHERE canvas_part = ? AND x = ? AND y = ? AND last_placed <= ? LIMIT 1"))
+            .await?;
+        let mut get_history = session
+            .prepare(format!("SELECT data FROM {ks}.canvas_history WHERE canvas_part = ? AND x=? AND y=? LIMIT ?"))
+            .await?;
+        let mut get_history_before = session
+            .prepare(format!("SELECT data FROM {ks}.canvas_history WHERE canvas_part = ? AND x = ? AND y = ? AND last_placed < ? LIMIT ?"))
+            .await?;
+        let mut get_part_cells = session
+            .prepare(format!(
+                "SELECT cells FROM {ks}.partition_cells WHERE canvas_part = ?"
+            ))
+            .await?;
+        let mut incr_part_cells = session
+            .prepare(format!(
+                "UPDATE {ks}.partition_cells SET cells = cells + ? WHERE canvas_part = ?"
+            ))
+            .await?;
+        let mut get_color_quota = session
+            .prepare(format!(
+                "SELECT placed FROM {ks}.color_quota WHERE day = ? AND color = ?"
+            ))
+            .await?;
+        let mut incr_color_quota = session
+            .prepare(format!(
+                "UPDATE {ks}.color_quota SET placed = placed + ? WHERE day = ? AND color = ?"
+            ))
+            .await?;
+        let mut get_daily_quota = session
+            .prepare(format!(
+                "SELECT placed FROM {ks}.daily_quota WHERE address = ? AND day = ?"
+            ))
+            .await?;
+        let mut set_daily_quota = session
+            .prepare(format!(
+                "INSERT INTO {ks}.daily_quota (address, day, placed) VALUES (?, ?, ?) USING TTL ?"
+            ))
+            .await?;
+        let mut get_stats = session
+            .prepare(format!(
+                "SELECT total_placed FROM {ks}.player_stats WHERE address = ?"
+            ))
+            .await?;
+        let mut incr_stats = session
+            .prepare(format!(
+                "UPDATE {ks}.player_stats SET total_placed = total_placed + ? WHERE address = ?"
+            ))
+            .await?;
+        // team column is left out of insert_user, so placements without a team keep it
+        // ink columns are left out of insert_user too, only ink budgets write them
+        let mut set_player_ink = session
+            .prepare(format!(
+                "UPDATE {ks}.player SET ink = ?, ink_updated_at = ? WHERE address = ?"
+            ))
+            .await?;
+        let mut set_player_team = session
+            .prepare(format!("UPDATE {ks}.player SET team = ? WHERE address = ?"))
+            .await?;
+        let mut incr_team = session
+            .prepare(format!(
+                "UPDATE {ks}.team_stats SET total_placed = total_placed + ? WHERE team = ?"
+            ))
+            .await?;
+        let mut insert_player_history = session
+            .prepare(format!("INSERT INTO {ks}.player_history (address, last_placed, x, y, color) VALUES (?, ?, ?, ?, ?)"))
+            .await?;
+        let mut get_player_history = session
+            .prepare(format!(
+                "SELECT x, y, color FROM {ks}.player_history WHERE address = ? LIMIT ?"
+            ))
             .await?;
+        // whole history of a user, paged
+        let mut scan_player_history = session
+            .prepare(format!(
+                "SELECT x, y, color, last_placed FROM {ks}.player_history WHERE address = ?"
+            ))
+            .await?;
+        set_paging(&mut scan_player_history, None, self.page_size);
+        // older buckets are never read, ttl keeps the table small : )
+        let mut insert_recent = session
+            .prepare(format!(
+                "INSERT INTO {ks}.recent_feed (bucket, last_placed, address, x, y, color, canvas_id) VALUES (?, ?, ?, ?, ?, ?, ?) USING TTL {}",
+                2 * RECENT_FEED_BUCKET_SECS
+            ))
+            .await?;
+        let mut get_recent = session
+            .prepare(format!(
+                "SELECT address, x, y, color FROM {ks}.recent_feed WHERE bucket = ? LIMIT ?"
+            ))
+            .await?;
+        let mut get_delta = session
+            .prepare(format!("SELECT canvas_id, address, x, y, color FROM {ks}.recent_feed WHERE bucket = ? AND last_placed > ?"))
+            .await?;
+        // the feed is shared by every canvas, a LIMIT would count the rows of others too
+        set_paging(&mut get_delta, None, self.page_size);
+        let mut scan_feed = session
+            .prepare(format!("SELECT canvas_id, color FROM {ks}.recent_feed WHERE bucket = ? AND last_placed > ?"))
+            .await?;
+        set_paging(&mut scan_feed, None, self.page_size);
+        let mut insert_user_pixel = session
+            .prepare(format!(
+                "INSERT INTO {ks}.user_pixels (address, canvas_part, x, y) VALUES (?, ?, ?, ?)"
+            ))
+            .await?;
+        let mut get_user_pixels = session
+            .prepare(format!(
+                "SELECT canvas_part, x, y FROM {ks}.user_pixels WHERE address = ?"
+            ))
+            .await?;
+        set_paging(&mut get_user_pixels, None, self.page_size);
+        let delete_user_pixels = session
+            .prepare(format!("DELETE FROM {ks}.user_pixels WHERE address = ?"))
+            .await?;
+        let mut incr_global = session
+            .prepare(format!(
+                "UPDATE {ks}.global_stats SET total_placed = total_placed + ? WHERE shard = ?"
+            ))
+            .await?;
+        let mut get_global = session
+            .prepare(format!(
+                "SELECT total_placed FROM {ks}.global_stats WHERE shard IN ?"
+            ))
+            .await?;
+        // full partition scan, paged
+        let mut scan_part = session
+            .prepare(format!(
+                "SELECT x, y, data FROM {ks}.canvas WHERE canvas_part = ?"
+            ))
+            .await?;
+        set_paging(&mut scan_part, None, self.page_size);
+        // clustering range delete of one column slice
+        let delete_pixel_col = session
+            .prepare(format!(
+                "DELETE FROM {ks}.canvas WHERE canvas_part = ? AND x = ? AND y >= ? AND y <= ?"
+            ))
+            .await?;
+        let delete_history = session
+            .prepare(format!("DELETE FROM {ks}.canvas_history WHERE canvas_part = ? AND x = ? AND y = ? AND last_placed = ?"))
+            .await?;
+        let mut scan_history = session
+            .prepare(format!(
+                "SELECT x, y, last_placed FROM {ks}.canvas_history WHERE canvas_part = ?"
+            ))
+            .await?;
+        set_paging(&mut scan_history, None, self.page_size);
+        let mut scan_history_colors = session
+            .prepare(format!(
+                "SELECT last_placed, data FROM {ks}.canvas_history WHERE canvas_part = ?"
+            ))
+            .await?;
+        set_paging(
+            &mut scan_history_colors,
+            Some(STATS_PAGE_SIZE),
+            self.page_size,
+        );
+        // every placement of one column slice of a partition
+        let mut get_history_col = session
+            .prepare(format!("SELECT y, last_placed, data FROM {ks}.canvas_history WHERE canvas_part = ? AND x = ? AND y >= ? AND y <= ?"))
+            .await?;
+        set_paging(&mut get_history_col, None, self.page_size);
+        // lets the driver's own retry policy retry these too
+        insert_user.set_is_idempotent(true);
+        insert_pixel.set_is_idempotent(true);
+        get_pixel.set_is_idempotent(true);
+        insert_history.set_is_idempotent(true);
+        insert_recent.set_is_idempotent(true);
+        // one column slice of a partition
+        let mut get_pixel_col = session
+            .prepare(format!("SELECT y, data FROM {ks}.canvas WHERE canvas_part = ? AND x = ? AND y >= ? AND y <= ?"))
+            .await?;
+        set_paging(&mut get_pixel_col, None, self.page_size);
+        let mut get_placement_key = session
+            .prepare(format!("SELECT idempotency_key FROM {ks}.placement_keys WHERE address = ? AND idempotency_key = ?"))
+            .await?;
+        // LWT, only the first placement with a key is applied : )
+        let claim_placement_key = session
+            .prepare(format!(
+                "INSERT INTO {ks}.placement_keys (address, idempotency_key) VALUES (?, ?) IF NOT EXISTS USING TTL {}",
+                self.idempotency_window.as_secs()
+            ))
+            .await?;
+        let delete_placement_key = session
+            .prepare(format!(
+                "DELETE FROM {ks}.placement_keys WHERE address = ? AND idempotency_key = ?"
+            ))
+            .await?;
+        // write time is the update time, so instances racing on the row keep the newest one
+        let mut touch_meta = session
+            .prepare(format!(
+                "UPDATE {ks}.canvas_meta USING TIMESTAMP ? SET last_update = ? WHERE canvas_id = ?"
+            ))
+            .await?;
+        let mut get_meta = session
+            .prepare(format!(
+                "SELECT last_update FROM {ks}.canvas_meta WHERE canvas_id = ?"
+            ))
+            .await?;
+        let mut count_part = session
+            .prepare(format!(
+                "SELECT COUNT(*) FROM {ks}.canvas WHERE canvas_part = ?"
+            ))
+            .await?;
+        let mut count_pixel_col = session
+            .prepare(format!("SELECT COUNT(*) FROM {ks}.canvas WHERE canvas_part = ? AND x = ? AND y >= ? AND y <= ?"))
+            .await?;
+        [
+            &mut count_part,
+            &mut get_meta,
+            &mut get_stats,
+            &mut get_color_quota,
+            &mut get_daily_quota,
+            &mut get_part_cells,
+            &mut get_placement_key,
+            &mut count_pixel_col,
+            &mut get_user,
+            &mut get_pixel,
+            &mut get_pixel_col,
+            &mut get_history,
+            &mut get_history_before,
+            &mut get_owner_at,
+            &mut get_global,
+            &mut get_user_pixels,
+            &mut get_player_history,
+            &mut get_recent,
+            &mut get_delta,
+            &mut scan_feed,
+            &mut scan_part,
+            &mut scan_history,
+            &mut scan_history_colors,
+            &mut get_history_col,
+            &mut scan_player_history,
+        ]
+        .into_iter()
+        .for_each(|stmt| set_level(stmt, self.read_consistency));
+        [
+            &mut insert_user,
+            &mut insert_pixel,
+            &mut insert_history,
+            &mut incr_stats,
+            &mut incr_team,
+            &mut set_player_team,
+            &mut set_player_ink,
+            &mut incr_global,
+            &mut incr_color_quota,
+            &mut set_daily_quota,
+            &mut incr_part_cells,
+            &mut insert_user_pixel,
+            &mut insert_player_history,
+            &mut insert_recent,
+            &mut touch_meta,
+        ]
+        .into_iter()
+        .for_each(|stmt| set_level(stmt, self.write_consistency));
+        let pixel_cache = match self.pixel_cache {
+            Some((redis, ttl)) => Some(PixelCache {
+                conn: redis.get_tokio_connection_manager().await?,
+                ttl,
+            }),
+            None => None,
+        };
+        // the default canvas keeps the legacy names when no canvas_id is set
+        let mut canvases = HashMap::new();
+        canvases.insert(
+            self.canvas_id
+                .clone()
+                .unwrap_or_else(|| DEFAULT_CANVAS.to_string()),
+            Canvas::new(
+                self.canvas_id.as_deref(),
+                CanvasConfig {
+                    canvas_dim: self.canvas_dim,
+                    shards: self.shards,
+                    color_mode: self.color_mode,
+                    reserved_regions: self.reserved_regions,
+                    region_cooldowns: self.region_cooldowns,
+                },
+            ),
+        );
+        for (canvas_id, config) in self.canvases {
+            let canvas = Canvas::new(Some(&canvas_id), config);
+            canvases.insert(canvas_id, canvas);
+        }
+        // partitions already past the threshold raise the hint right away
+        let mut needs_resharding = false;
+        for canvas in canvases.values() {
+            for (part, cells) in canvas.canvas_part.iter().zip(&canvas.cells) {
+                let rows = session.execute(&get_part_cells, (part,)).await?;
+                let count = match rows.first_row_typed::<(Counter,)>() {
+                    Ok((count,)) => count.0,
+                    Err(FirstRowTypedError::RowsEmpty) => 0,
+                    Err(e) => return Err(VpError::ScyllaTypeErr(e)),
+                };
+                cells.store(count, Ordering::Relaxed);
+                if count >= self.reshard_threshold {
+                    log::warn!(
+                        "Partition {} holds ~{} cells, consider resharding",
+                        part,
+                        count
+                    );
+                    needs_resharding = true;
+                }
+            }
+        }
+        if let Some(check) = self.palette_check {
+            let sample_part = session
+                .prepare(format!(
+                    "SELECT data FROM {ks}.canvas WHERE canvas_part = ? LIMIT {PALETTE_SAMPLE_ROWS}"
+                ))
+                .await?;
+            for (canvas_id, canvas) in &canvases {
+                let ColorMode::Palette(palette) = &canvas.color_mode else {
+                    continue;
+                };
+                for part in &canvas.canvas_part {
+                    let rows = session.execute(&sample_part, (part,)).await?;
+                    for row in rows.rows_typed_or_empty::<(PixelData,)>() {
+                        let (pixel,) = row?;
+                        if palette.contains(pixel.color) {
+                            continue;
+                        }
+                        let err = VpError::PaletteMismatch {
+                            canvas: canvas_id.clone(),
+                            color: pixel.color,
+                            colors: palette.colors.len(),
+                        };
+                        match check {
+                            PaletteCheck::Warn => log::warn!("{}", err),
+                            PaletteCheck::Error => return Err(err),
+                        }
+                        break;
+                    }
+                }
+            }
+        }
         Ok(ScyllaManager {
-            session: self.session,
-            dim_mid: self.dim_mid,
+            session: Arc::new(session),
+            pixel_cache,
+            health_timeout: self.health_timeout,
+            op_timeout: self.op_timeout,
+            page_size: self.page_size,
+            retry: self.retry,
+            metrics: self.metrics,
+            canvases,
+            rules: PlacementRules {
+                cooldown: self.cooldown,
+                cooldown_scaling: self.cooldown_scaling,
+                ink: self.ink,
+                free_placements: self.free_placements,
+                admin_addresses: self.admin_addresses,
+            },
+            daily_quota: self.daily_quota,
+            color_quotas: self.color_quotas,
+            teams: self.teams,
+            render_background: self.render_background,
+            background_color: self.background_color,
+            updates: broadcast::channel(self.update_capacity).0,
+            event_sink: self.event_sink,
+            keyspace: self.keyspace,
+            reshard_threshold: self.reshard_threshold,
+            needs_resharding: AtomicBool::new(needs_resharding),
+            writes: WriteGate::default(),
             insert_user,
             get_user,
             insert_pixel,
             get_pixel,
-            canvas_part: ["v_part1", "v_part2", "v_part3", "v_part4"],
+            get_pixel_col,
+            count_pixel_col,
+            count_part,
+            touch_meta,
+            get_meta,
+            get_placement_key,
+            claim_placement_key,
+            delete_placement_key,
+            insert_history,
+            get_history,
+            get_history_before,
+            insert_pixel_if_empty,
+            update_pixel_if,
+            claim_player,
+            claim_new_player,
+            get_owner_at,
+            get_stats,
+            incr_stats,
+            set_player_team,
+            set_player_ink,
+            incr_team,
+            get_color_quota,
+            get_daily_quota,
+            set_daily_quota,
+            incr_part_cells,
+            incr_color_quota,
+            incr_global,
+            get_global,
+            insert_player_history,
+            get_player_history,
+            scan_player_history,
+            insert_recent,
+            get_recent,
+            get_delta,
+            scan_feed,
+            insert_user_pixel,
+            get_user_pixels,
+            delete_user_pixels,
+            scan_part,
+            scan_history,
+            scan_history_colors,
+            get_history_col,
+            delete_pixel_col,
+            delete_history,
         })
     }
 }
 
-//ScyllaDb Manager
-pub struct ScyllaManager {
-    session: Session,
-    dim_mid: u32,
-    insert_user: PreparedStatement,
-    get_user: PreparedStatement,
-    insert_pixel: PreparedStatement,
-    get_pixel: PreparedStatement,
-    canvas_part: [&'static str; 4],
+// key of the default canvas when the builder has no canvas_id
+const DEFAULT_CANVAS: &str = "default";
+
+// dimension, partitioning and colors of one canvas
+pub struct CanvasConfig {
+    pub canvas_dim: u32,
+    pub shards: u32,
+    pub color_mode: ColorMode,
+    pub reserved_regions: Vec<Rect>,
+    // (region, cooldown secs), the smallest match wins
+    pub region_cooldowns: Vec<(Rect, i64)>,
 }
-impl ScyllaManager {
-    pub async fn get_user(&self, address: &String) -> Result<UserDetails, VpError> {
-        let rows = self.session.execute(&self.get_user, (address,)).await?;
-        let res = rows.first_row_typed::<UserDetails>();
-        match res {
-            Ok(res) => Ok(res),
-            Err(FirstRowTypedError::RowsEmpty) => Err(VpError::InvalidUser),
-            Err(e) => Err(VpError::ScyllaTypeErr(e)),
+
+// what PixelData.color holds on a canvas
+#[derive(Clone)]
+pub enum ColorMode {
+    // index into the palette
+    Palette(Palette),
+    // packed 0xRRGGBB, 3 bytes per cell in packed regions. meant for extra canvases,
+    // the redis canvas and the http placement routes only know 4 bit palette indexes
+    Truecolor,
+}
+impl ColorMode {
+    fn contains(&self, color: i32) -> bool {
+        match self {
+            ColorMode::Palette(palette) => palette.contains(color),
+            ColorMode::Truecolor => (0..=0xFF_FFFF).contains(&color),
         }
     }
-    pub async fn update_db(&self, req: &UpdatePixel) -> Result<(), VpError> {
-        let (ix, iy) = (i32::try_from(req.loc.x)?, i32::try_from(req.loc.y)?);
-        // infallible :)
-        let color = i32::try_from(req.color).unwrap();
-        //already checked in handler
-        let address = req.address.as_ref().ok_or_else(|| VpError::InvalidUser)?;
-        let last_placed = Utc::now().timestamp();
+    // color of a request as stored, never panics on a u32 past i32
+    // palette index or 0xRRGGBB, anything past i32 is invalid in both modes : )
+    fn check_color(&self, color: u32) -> Result<i32, VpError> {
+        let color = i32::try_from(color).unwrap_or(i32::MAX);
+        if self.contains(color) {
+            Ok(color)
+        } else {
+            Err(VpError::InvalidColor(color))
+        }
+    }
+    // bytes of one cell in packed regions / mipmaps
+    fn cell_bytes(&self) -> usize {
+        match self {
+            ColorMode::Palette(_) => 1,
+            ColorMode::Truecolor => 3,
+        }
+    }
+    // color written into a cell_bytes long slice, rgb big endian for truecolor
+    fn encode(&self, color: i32, cell: &mut [u8]) {
+        match self {
+            ColorMode::Palette(_) => cell[0] = u8::try_from(color).unwrap_or_default(),
+            ColorMode::Truecolor => cell.copy_from_slice(&unpack_rgb(color)),
+        }
+    }
+}
+impl Default for ColorMode {
+    fn default() -> Self {
+        ColorMode::Palette(Palette::default())
+    }
+}
 
-        // add user update
-        let user_update = self
-            .session
-            .execute(&self.insert_user, (address, ix, iy, color, last_placed));
+// inclusive corners, (x0,y0) top left
+#[derive(Clone, Copy, Serialize)]
+pub struct Rect {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+impl Rect {
+    pub fn new(x0: u32, y0: u32, x1: u32, y1: u32) -> Self {
+        Self {
+            x0: x0.min(x1),
+            y0: y0.min(y1),
+            x1: x0.max(x1),
+            y1: y0.max(y1),
+        }
+    }
+    fn contains(&self, x: u32, y: u32) -> bool {
+        (self.x0..=self.x1).contains(&x) && (self.y0..=self.y1).contains(&y)
+    }
+    fn area(&self) -> u64 {
+        (u64::from(self.x1 - self.x0) + 1) * (u64::from(self.y1 - self.y0) + 1)
+    }
+}
 
-        // add  pixel update
-        let pindex = match (req.loc.x <= self.dim_mid, req.loc.y <= self.dim_mid) {
-            (true, true) => 0,
-            (true, false) => 1,
-            (false, true) => 2,
-            (false, false) => 3,
-        };
-        let pixel_data = PixelData {
-            address: address.to_string(),
-            color,
-            last_placed,
+// a canvas of the manager, partition names are built once : )
+struct Canvas {
+    canvas_dim: u32,
+    shards: u32,
+    // cells per shard along each axis
+    shard_size: u32,
+    color_mode: ColorMode,
+    reserved_regions: Vec<Rect>,
+    region_cooldowns: Vec<(Rect, i64)>,
+    canvas_part: Vec<String>,
+    // last canvas_meta write of this instance, seconds
+    touched: AtomicI64,
+    // (counted at, fill percentage)
+    fill: Mutex<Option<(Instant, f64)>>,
+    // estimated history rows per canvas_part, same order
+    cells: Vec<AtomicI64>,
+}
+impl Canvas {
+    // prefix None keeps the legacy v_part{n} names
+    fn new(prefix: Option<&str>, config: CanvasConfig) -> Self {
+        let shards = config.shards.max(1);
+        // +1 keeps the shards=2 split identical to the old dim_mid quadrants
+        let shard_size = config.canvas_dim / shards + 1;
+        let canvas_part = (1..=shards * shards)
+            .map(|part| match prefix {
+                Some(canvas_id) => format!("{}_p{}", canvas_id, part),
+                None => format!("v_part{}", part),
+            })
+            .collect::<Vec<_>>();
+        Self {
+            cells: canvas_part.iter().map(|_| AtomicI64::new(0)).collect(),
+            canvas_dim: config.canvas_dim,
+            shards,
+            shard_size,
+            color_mode: config.color_mode,
+            reserved_regions: config.reserved_regions,
+            region_cooldowns: config.region_cooldowns,
+            canvas_part,
+            touched: AtomicI64::new(0),
+            fill: Mutex::new(None),
+        }
+    }
+    // cooldown of the smallest region holding (x,y), default outside all of them
+    fn cooldown_at(&self, x: u32, y: u32, default: i64) -> i64 {
+        self.region_cooldowns
+            .iter()
+            .filter(|(rect, _)| rect.contains(x, y))
+            .min_by_key(|(rect, _)| rect.area())
+            .map_or(default, |(_, cooldown)| *cooldown)
+    }
+    fn is_reserved(&self, x: u32, y: u32) -> bool {
+        self.reserved_regions.iter().any(|rect| rect.contains(x, y))
+    }
+    // no phantom pixels outside the rendered canvas
+    fn check_bounds(&self, x: u32, y: u32) -> Result<(), VpError> {
+        if x < self.canvas_dim && y < self.canvas_dim {
+            Ok(())
+        } else {
+            Err(VpError::OutOfBounds { x, y })
+        }
+    }
+    // (x0,y0) top left and (x1,y1) bottom right corner, both inside the canvas
+    fn check_region(&self, x0: u32, y0: u32, x1: u32, y1: u32) -> Result<(), VpError> {
+        if x0 > x1 || y0 > y1 {
+            return Err(VpError::InvalidRegion { x0, y0, x1, y1 });
+        }
+        self.check_bounds(x1, y1)
+    }
+    // split a region along shard boundaries into the (pindex, x range, y range) it overlaps
+    fn split_region(
+        &self,
+        xr: RangeInclusive<u32>,
+        yr: RangeInclusive<u32>,
+    ) -> Vec<(usize, RangeInclusive<u32>, RangeInclusive<u32>)> {
+        let slices = |r: &RangeInclusive<u32>| {
+            (r.start() / self.shard_size..=r.end() / self.shard_size)
+                .map(|i| {
+                    let start = (*r.start()).max(i * self.shard_size);
+                    let end = (*r.end()).min((i + 1) * self.shard_size - 1);
+                    (i, start..=end)
+                })
+                .collect::<Vec<_>>()
         };
-        let pixel_update = self.session.execute(
-            &self.insert_pixel,
-            (self.canvas_part[pindex], ix, iy, pixel_data),
-        );
-        tokio::try_join!(user_update, pixel_update)?;
-        Ok(())
+        let ys = slices(&yr);
+        let mut parts = Vec::new();
+        for (xi, xs) in slices(&xr) {
+            for (yi, ys) in ys.iter() {
+                parts.push(((xi * self.shards + yi) as usize, xs.clone(), ys.clone()));
+            }
+        }
+        parts
     }
-    pub async fn get_pixel(&self, x: u32, y: u32) -> Result<PixelData, VpError> {
-        let ix = i32::try_from(x)?;
-        let iy = i32::try_from(y)?;
-        let pindex = match (x <= self.dim_mid, y <= self.dim_mid) {
-            (true, true) => 0,
-            (true, false) => 1,
-            (false, true) => 2,
-            (false, false) => 3,
+    // every shard with the cells it covers, in canvas_part order
+    fn partition_infos(&self) -> Vec<PartitionInfo> {
+        let bounds = |i: u32| {
+            let start = (i * self.shard_size).min(self.canvas_dim);
+            start..((i + 1) * self.shard_size).min(self.canvas_dim)
         };
-        let rows = self
-            .session
-            .execute(&self.get_pixel, (self.canvas_part[pindex], ix, iy))
-            .await?;
-        let res = rows.first_row_typed::<(PixelData,)>();
+        self.canvas_part
+            .iter()
+            .enumerate()
+            .map(|(pindex, name)| {
+                let pindex = pindex as u32;
+                PartitionInfo {
+                    name: name.clone(),
+                    x_range: bounds(pindex / self.shards),
+                    y_range: bounds(pindex % self.shards),
+                }
+            })
+            .collect()
+    }
+    // index of the shard holding (x,y), row major over the shards x shards grid
+    fn partition_index(&self, x: u32, y: u32) -> usize {
+        ((x / self.shard_size) * self.shards + y / self.shard_size) as usize
+    }
+    // canvas_part key of the shard holding (x,y), eg: opbnbplace_1_p1
+    fn partition_name(&self, x: u32, y: u32) -> &str {
+        &self.canvas_part[self.partition_index(x, y)]
+    }
+}
+
+// redis cache of hot pixels
+struct PixelCache {
+    conn: ConnectionManager,
+    ttl: usize,
+}
+impl PixelCache {
+    // partition names are unique per canvas, so they namespace the keys : )
+    fn key(part: &str, x: u32, y: u32) -> String {
+        format!("px:{}:{}:{}", part, x, y)
+    }
+    async fn get(&self, part: &str, x: u32, y: u32) -> Option<PixelData> {
+        let res = redis::Cmd::get(Self::key(part, x, y))
+            .query_async::<_, Option<String>>(&mut self.conn.clone())
+            .await;
         match res {
-            Ok(res) => Ok(res.0),
-            Err(FirstRowTypedError::RowsEmpty) => Err(VpError::NoPixelData),
-            Err(e) => Err(VpError::ScyllaTypeErr(e)),
+            Ok(pixel) => pixel.and_then(|p| serde_json::from_str(&p).ok()),
+            Err(e) => {
+                log::warn!("Pixel cache read failed : {}", e);
+                None
+            }
+        }
+    }
+    async fn del(&self, part: &str, x: u32, y: u32) {
+        let res = redis::Cmd::del(Self::key(part, x, y))
+            .query_async::<_, ()>(&mut self.conn.clone())
+            .await;
+        if let Err(e) = res {
+            log::warn!("Pixel cache delete failed : {}", e);
+        }
+    }
+    async fn set(&self, part: &str, x: u32, y: u32, pixel: &PixelData) {
+        let Ok(pixel) = serde_json::to_string(pixel) else {
+            return;
+        };
+        let res = redis::Cmd::set_ex(Self::key(part, x, y), pixel, self.ttl)
+            .query_async::<_, ()>(&mut self.conn.clone())
+            .await;
+        if let Err(e) = res {
+            log::warn!("Pixel cache write failed : {}", e);
         }
     }
 }
 
-//ScyllaDb RowData
-#[derive(FromRow)]
-pub struct UserDetails {
-    pub address: String,
-    pub x: i32,     //u32 aan sherikkum , but CQL derive does'nt support : )
-    pub y: i32,     // same as above : )
-    pub color: i32, // sherikkum u8
-    pub last_placed: i64,
+// counts in flight writes, closed on shutdown
+#[derive(Default)]
+struct WriteGate {
+    closed: AtomicBool,
+    // maintenance mode, writes are refused but reads keep working
+    read_only: AtomicBool,
+    in_flight: AtomicUsize,
+    drained: Notify,
+}
+impl WriteGate {
+    // counted before the closed check, so close never misses a write that got in : )
+    fn enter(&self) -> Result<WriteGuard<'_>, VpError> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let guard = WriteGuard(self);
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(VpError::ShuttingDown);
+        }
+        if self.read_only.load(Ordering::SeqCst) {
+            return Err(VpError::ReadOnlyMode);
+        }
+        Ok(guard)
+    }
+    async fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        loop {
+            // registered before the count is read, a drain in between still wakes it
+            let drained = self.drained.notified();
+            if self.in_flight.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            drained.await;
+        }
+    }
+}
+struct WriteGuard<'a>(&'a WriteGate);
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.drained.notify_waiters();
+        }
+    }
 }
 
-#[derive(IntoUserType, FromUserType, Serialize)]
-pub struct PixelData {
-    pub address: String,
-    pub color: i32,
-    pub last_placed: i64,
+// db_ops_total{op,status}, db_errors_total{op,error}, db_op_duration_seconds{op}
+// metrics do nothing when disabled, not even reading the clock : )
+// errors also tag the current tracing span with the VpError variant
+// an op past its deadline is dropped as OperationTimeout, writes it started may still land
+async fn instrument<T>(
+    enabled: bool,
+    timeout: Duration,
+    op: &'static str,
+    fut: impl Future<Output = Result<T, VpError>>,
+) -> Result<T, VpError> {
+    let start = enabled.then(Instant::now);
+    let res = tokio::time::timeout(timeout, fut)
+        .await
+        .unwrap_or(Err(VpError::OperationTimeout));
+    if let Err(e) = &res {
+        Span::current().record("error", e.name());
+    }
+    let Some(start) = start else {
+        return res;
+    };
+    metrics::histogram!("db_op_duration_seconds", "op" => op).record(start.elapsed().as_secs_f64());
+    match &res {
+        Ok(_) => metrics::counter!("db_ops_total", "op" => op, "status" => "ok").increment(1),
+        Err(e) => {
+            metrics::counter!("db_ops_total", "op" => op, "status" => "error").increment(1);
+            metrics::counter!("db_errors_total", "op" => op, "error" => e.name()).increment(1);
+        }
+    }
+    res
+}
+
+// who waits how long between placements, builder time state only
+// no db access, check_cooldown reads the player row and the grace counter for it
+struct PlacementRules {
+    cooldown: i64,
+    cooldown_scaling: Option<CooldownScaling>,
+    ink: Option<InkBudget>,
+    free_placements: i64,
+    // lowercase, see with_admin_addresses
+    admin_addresses: HashSet<String>,
+}
+impl PlacementRules {
+    fn is_admin(&self, address: &str) -> bool {
+        self.admin_addresses.contains(&address.to_lowercase())
+    }
+    // a wallet with placed placements so far still skips the cooldown
+    fn in_grace(&self, placed: i64) -> bool {
+        placed < self.free_placements
+    }
+    fn scaled_cooldown(&self, cooldown_secs: i64, streak: i32) -> i64 {
+        match self.cooldown_scaling {
+            Some(scaling) => {
+                let max_secs = scaling.max_secs.max(cooldown_secs);
+                (cooldown_secs as f64 * scaling.factor.powi(streak)).min(max_secs as f64) as i64
+            }
+            None => cooldown_secs,
+        }
+    }
+    // rows from before ink budgets (or cooldown mode) start full
+    fn current_ink(ink: InkBudget, user: &UserDetails, now: i64) -> (i64, i64) {
+        ink.refill(
+            user.ink.unwrap_or(ink.max),
+            user.ink_updated_at.unwrap_or(user.last_placed),
+            now,
+        )
+    }
+    // count pixels of address in one batch, past the first every pixel is a placement
+    // within the cooldown. ink is charged per pixel by the batch itself, admins are exempt
+    fn check_stamp(&self, address: &str, count: i64, cooldown_secs: i64) -> Result<(), VpError> {
+        if count > 1 && self.ink.is_none() && !self.is_admin(address) {
+            return Err(VpError::CooldownActive {
+                remaining_secs: cooldown_secs.max(1),
+            });
+        }
+        Ok(())
+    }
+    // streak / nonce the next placement of address goes with, user None : never placed.
+    // in_grace skips the cooldown for a wallet still within its free placements
+    fn next_placement(
+        &self,
+        address: &str,
+        user: Option<&UserDetails>,
+        cooldown_secs: i64,
+        now: i64,
+        in_grace: bool,
+    ) -> Result<NextPlacement, VpError> {
+        let exempt = |nonce, ink| NextPlacement {
+            streak: 0,
+            nonce,
+            cooldown_secs: 0,
+            ink,
+            daily_placed: None,
+        };
+        let Some(user) = user else {
+            return Ok(exempt(0, self.ink.map(|ink| (ink.max, now))));
+        };
+        // rows from before nonces start at 0
+        let nonce = u64::try_from(user.nonce.unwrap_or(0))?;
+        if self.is_admin(address) {
+            return Ok(exempt(nonce, None));
+        }
+        if let Some(ink) = self.ink {
+            let (available, at) = Self::current_ink(ink, user, now);
+            if available < ink.cost {
+                return Err(VpError::InsufficientInk {
+                    available,
+                    required: ink.cost,
+                });
+            }
+            return Ok(exempt(nonce, Some((available, at))));
+        }
+        let elapsed = now - user.last_placed;
+        let streak = user.streak.unwrap_or(0);
+        let cooldown_secs = self.scaled_cooldown(cooldown_secs, streak);
+        if elapsed < cooldown_secs && in_grace {
+            return Ok(exempt(nonce, None));
+        }
+        if elapsed < cooldown_secs {
+            return Err(VpError::CooldownActive {
+                remaining_secs: cooldown_secs - elapsed,
+            });
+        }
+        let streak = match self.cooldown_scaling {
+            Some(scaling) if elapsed <= scaling.reset_after_secs => streak.saturating_add(1),
+            _ => 0,
+        };
+        Ok(NextPlacement {
+            streak,
+            nonce,
+            cooldown_secs,
+            ink: None,
+            daily_placed: None,
+        })
+    }
+}
+
+//ScyllaDb Manager
+pub struct ScyllaManager {
+    // shared with long running streams
+    session: Arc<Session>,
+    pixel_cache: Option<PixelCache>,
+    health_timeout: Duration,
+    op_timeout: Duration,
+    // rows per page of range reads, unless the call picks its own
+    page_size: i32,
+    retry: RetryConfig,
+    metrics: bool,
+    canvases: HashMap<String, Canvas>,
+    rules: PlacementRules,
+    daily_quota: i64,
+    color_quotas: HashMap<i32, i64>,
+    teams: HashSet<String>,
+    render_background: [u8; 3],
+    background_color: i32,
+    updates: broadcast::Sender<PixelUpdate>,
+    event_sink: Option<Arc<dyn EventSink>>,
+    keyspace: String,
+    reshard_threshold: i64,
+    needs_resharding: AtomicBool,
+    writes: WriteGate,
+    insert_user: PreparedStatement,
+    get_user: PreparedStatement,
+    insert_pixel: PreparedStatement,
+    get_pixel: PreparedStatement,
+    get_pixel_col: PreparedStatement,
+    count_pixel_col: PreparedStatement,
+    count_part: PreparedStatement,
+    touch_meta: PreparedStatement,
+    get_meta: PreparedStatement,
+    get_placement_key: PreparedStatement,
+    claim_placement_key: PreparedStatement,
+    delete_placement_key: PreparedStatement,
+    insert_history: PreparedStatement,
+    get_history: PreparedStatement,
+    get_history_before: PreparedStatement,
+    insert_pixel_if_empty: PreparedStatement,
+    update_pixel_if: PreparedStatement,
+    claim_player: PreparedStatement,
+    claim_new_player: PreparedStatement,
+    get_owner_at: PreparedStatement,
+    get_stats: PreparedStatement,
+    incr_stats: PreparedStatement,
+    set_player_team: PreparedStatement,
+    set_player_ink: PreparedStatement,
+    incr_team: PreparedStatement,
+    get_color_quota: PreparedStatement,
+    get_daily_quota: PreparedStatement,
+    set_daily_quota: PreparedStatement,
+    incr_part_cells: PreparedStatement,
+    incr_color_quota: PreparedStatement,
+    incr_global: PreparedStatement,
+    get_global: PreparedStatement,
+    insert_player_history: PreparedStatement,
+    get_player_history: PreparedStatement,
+    scan_player_history: PreparedStatement,
+    insert_recent: PreparedStatement,
+    get_recent: PreparedStatement,
+    get_delta: PreparedStatement,
+    scan_feed: PreparedStatement,
+    insert_user_pixel: PreparedStatement,
+    get_user_pixels: PreparedStatement,
+    delete_user_pixels: PreparedStatement,
+    scan_part: PreparedStatement,
+    scan_history: PreparedStatement,
+    scan_history_colors: PreparedStatement,
+    get_history_col: PreparedStatement,
+    delete_pixel_col: PreparedStatement,
+    delete_history: PreparedStatement,
+}
+impl ScyllaManager {
+    fn canvas(&self, canvas_id: &str) -> Result<&Canvas, VpError> {
+        self.canvases
+            .get(canvas_id)
+            .ok_or_else(|| VpError::UnknownCanvas(canvas_id.to_string()))
+    }
+    // stops accepting placements (ShuttingDown) and waits for the ones in flight,
+    // so a rolling deploy never leaves a half applied placement behind
+    pub async fn shutdown(&self) {
+        self.writes.close().await;
+    }
+    // placements and admin writes fail with ReadOnlyMode while set, the ones in flight still finish
+    pub fn set_read_only(&self, ro: bool) {
+        self.writes.read_only.store(ro, Ordering::SeqCst);
+    }
+    pub fn is_read_only(&self) -> bool {
+        self.writes.read_only.load(Ordering::SeqCst)
+    }
+    // some canvas_part grew past the reshard threshold since startup or before it,
+    // nothing is resharded, it's a heads up for ops before writes start timing out
+    pub fn needs_resharding(&self) -> bool {
+        self.needs_resharding.load(Ordering::Relaxed)
+    }
+    // counts written cells, warns once per partition when it crosses the threshold
+    fn count_cells(&self, canvas: &Canvas, pindex: usize, count: i64) {
+        let cells = canvas.cells[pindex].fetch_add(count, Ordering::Relaxed) + count;
+        if cells >= self.reshard_threshold && cells - count < self.reshard_threshold {
+            log::warn!(
+                "Partition {} holds ~{} cells, consider resharding",
+                canvas.canvas_part[pindex],
+                cells
+            );
+            self.needs_resharding.store(true, Ordering::Relaxed);
+        }
+    }
+    // readiness probe, just pings the cluster : )
+    pub async fn health_check(&self) -> Result<(), VpError> {
+        let ping = self.session.query("SELECT now() FROM system.local", &[]);
+        tokio::time::timeout(self.health_timeout, ping)
+            .await
+            .map_err(|_| VpError::OperationTimeout)??;
+        Ok(())
+    }
+    #[tracing::instrument(name = "get_user", skip_all, fields(address = %address, error = Empty))]
+    pub async fn get_user(&self, address: &String) -> Result<UserDetails, VpError> {
+        let address = normalize_address(address)?;
+        let fut = self
+            .fetch_user(&address)
+            .map_err(VpError::user_read(&address));
+        instrument(self.metrics, self.op_timeout, "get_user", fut).await
+    }
+    async fn fetch_user(&self, address: &String) -> Result<UserDetails, VpError> {
+        let rows = self.session.execute(&self.get_user, (address,)).await?;
+        let res = rows.first_row_typed::<UserDetails>();
+        match res {
+            Ok(res) => Ok(res),
+            Err(FirstRowTypedError::RowsEmpty) => Err(VpError::InvalidUser),
+            Err(e) => Err(VpError::ScyllaTypeErr(e)),
+        }
+    }
+    // users in input order, None for unknown addresses
+    pub async fn get_users(
+        &self,
+        addresses: &[String],
+    ) -> Result<Vec<Option<UserDetails>>, VpError> {
+        let ks = &self.keyspace;
+        if addresses.len() > MAX_USERS_BATCH {
+            return Err(VpError::BatchTooLarge);
+        }
+        if addresses.is_empty() {
+            return Ok(Vec::new());
+        }
+        let addresses = addresses
+            .iter()
+            .map(|address| normalize_address(address))
+            .collect::<Result<Vec<_>, _>>()?;
+        // one bind marker per address, so the statement is prepared per slice length
+        let markers = vec!["?"; addresses.len()].join(",");
+        let mut stmt = self
+            .session
+            .prepare(format!(
+                "SELECT address, x, y, color, last_placed, streak, nonce, ink, ink_updated_at FROM {ks}.player WHERE address IN ({})",
+                markers
+            ))
+            .await?;
+        if let Some(level) = self.get_user.get_consistency() {
+            stmt.set_consistency(level);
+        }
+        let rows = self.session.execute(&stmt, &addresses).await?;
+        let found: HashMap<String, UserDetails> = rows
+            .rows_typed_or_empty::<UserDetails>()
+            .map(|row| row.map(|user| (user.address.clone(), user)))
+            .collect::<Result<_, _>>()?;
+        Ok(addresses.iter().map(|a| found.get(a).cloned()).collect())
+    }
+    // cooldown check, Ok is the streak / nonce the next placement of address goes with
+    pub async fn check_cooldown(
+        &self,
+        address: &String,
+        cooldown_secs: i64,
+    ) -> Result<NextPlacement, VpError> {
+        let user = match self.get_user(address).await {
+            Ok(user) => Some(user),
+            // never placed before : )
+            Err(VpError::InvalidUser) => None,
+            Err(e) => return Err(e),
+        };
+        let now = Utc::now().timestamp();
+        let next = self
+            .rules
+            .next_placement(address, user.as_ref(), cooldown_secs, now, false);
+        match next {
+            // the counter is only read when the cooldown would reject
+            Err(VpError::CooldownActive { .. }) if self.in_grace(address).await? => self
+                .rules
+                .next_placement(address, user.as_ref(), cooldown_secs, now, true),
+            next => next,
+        }
+    }
+    // stores the balance left after count placements, nothing to do without an ink budget
+    async fn spend_ink(
+        &self,
+        address: &str,
+        next: &NextPlacement,
+        count: i64,
+    ) -> Result<(), VpError> {
+        let (Some(ink), Some((available, at))) = (self.rules.ink, next.ink) else {
+            return Ok(());
+        };
+        let left = available - ink.cost.saturating_mul(count);
+        self.session
+            .execute(&self.set_player_ink, (left, at, address))
+            .await?;
+        Ok(())
+    }
+    // count more placements of color still fit in today's quota
+    // like the cooldown, concurrent placements can race past it by a few
+    async fn check_color_quota(&self, color: i32, count: i64) -> Result<(), VpError> {
+        let Some(quota) = self.color_quotas.get(&color) else {
+            return Ok(());
+        };
+        let rows = self
+            .session
+            .execute(&self.get_color_quota, (quota_day(), color))
+            .await?;
+        let placed = match rows.first_row_typed::<(Counter,)>() {
+            Ok((placed,)) => placed.0,
+            Err(FirstRowTypedError::RowsEmpty) => 0,
+            Err(e) => return Err(VpError::ScyllaTypeErr(e)),
+        };
+        if !fits_quota(placed, count, *quota) {
+            return Err(VpError::ColorQuotaExceeded { color });
+        }
+        Ok(())
+    }
+    // only colors with a quota are counted
+    async fn incr_color_quota(&self, color: i32, count: i64) -> Result<(), VpError> {
+        if self.color_quotas.contains_key(&color) {
+            self.session
+                .execute(&self.incr_color_quota, (Counter(count), quota_day(), color))
+                .await?;
+        }
+        Ok(())
+    }
+    // count more placements of address still fit in its quota of today,
+    // Some((day, placed)) to count them with, None when not limited
+    // like the cooldown, concurrent placements can race past it by a few
+    async fn check_daily_quota(
+        &self,
+        address: &str,
+        count: i64,
+    ) -> Result<Option<(i64, i64)>, VpError> {
+        if self.daily_quota == 0 || self.is_admin(address) {
+            return Ok(None);
+        }
+        let day = quota_day();
+        let rows = self
+            .session
+            .execute(&self.get_daily_quota, (address, day))
+            .await?;
+        let placed = match rows.first_row_typed::<(i64,)>() {
+            Ok((placed,)) => placed,
+            Err(FirstRowTypedError::RowsEmpty) => 0,
+            Err(e) => return Err(VpError::ScyllaTypeErr(e)),
+        };
+        if !fits_quota(placed, count, self.daily_quota) {
+            return Err(VpError::DailyQuotaExceeded {
+                placed,
+                limit: self.daily_quota,
+            });
+        }
+        Ok(Some((day, placed)))
+    }
+    // stores the day's placements after count more, the row expires when the day ends
+    async fn count_daily(
+        &self,
+        address: &str,
+        next: &NextPlacement,
+        count: i64,
+    ) -> Result<(), VpError> {
+        let Some((day, placed)) = next.daily_placed else {
+            return Ok(());
+        };
+        let ttl = i32::try_from(secs_to_day_end(day, Utc::now().timestamp()))?;
+        self.session
+            .execute(&self.set_daily_quota, (address, day, placed + count, ttl))
+            .await?;
+        Ok(())
+    }
+    // still within the free placements of a new wallet
+    async fn in_grace(&self, address: &str) -> Result<bool, VpError> {
+        if self.rules.free_placements == 0 {
+            return Ok(false);
+        }
+        let rows = self.session.execute(&self.get_stats, (address,)).await?;
+        let placed = match rows.first_row_typed::<(Counter,)>() {
+            Ok((placed,)) => placed.0,
+            Err(FirstRowTypedError::RowsEmpty) => 0,
+            Err(e) => return Err(VpError::ScyllaTypeErr(e)),
+        };
+        Ok(self.rules.in_grace(placed))
+    }
+    // reserved cells are for admin addresses only
+    fn check_reserved(
+        &self,
+        canvas: &Canvas,
+        x: u32,
+        y: u32,
+        address: &str,
+    ) -> Result<(), VpError> {
+        if canvas.is_reserved(x, y) && !self.is_admin(address) {
+            return Err(VpError::RegionReserved { x, y });
+        }
+        Ok(())
+    }
+    // timestamp the placement is stored with
+    // only admin addresses may backdate, anyone else could skip the cooldown with it : )
+    fn placed_at(&self, req: &UpdatePixel, address: &str, now: i64) -> Result<i64, VpError> {
+        match req.placed_at {
+            None => Ok(now),
+            Some(ts) if ts < 0 || ts > now + MAX_CLOCK_SKEW_SECS || !self.is_admin(address) => {
+                Err(VpError::InvalidTimestamp(ts))
+            }
+            Some(ts) => Ok(ts),
+        }
+    }
+    fn check_tag(&self, req: &UpdatePixel) -> Result<(), VpError> {
+        match &req.tag {
+            Some(tag) if tag.chars().count() > MAX_TAG_LEN => Err(VpError::TagTooLong),
+            _ => Ok(()),
+        }
+    }
+    fn check_team(&self, req: &UpdatePixel) -> Result<(), VpError> {
+        match &req.team {
+            Some(team) if !self.teams.contains(team) => Err(VpError::UnknownTeam(team.clone())),
+            _ => Ok(()),
+        }
+    }
+    fn is_admin(&self, address: &str) -> bool {
+        self.rules.is_admin(address)
+    }
+    // seconds left before the user may place again, 0 if ready : )
+    pub async fn get_cooldown_remaining(&self, address: &String) -> Result<i64, VpError> {
+        if self.is_admin(address) {
+            return Ok(0);
+        }
+        match self.get_user(address).await {
+            Ok(user) => {
+                // time until the ink covers one placement
+                if let Some(ink) = self.rules.ink {
+                    let now = Utc::now().timestamp();
+                    let (available, at) = PlacementRules::current_ink(ink, &user, now);
+                    let missing = (ink.cost - available).max(0);
+                    let ready_at = at + missing * ink.refill_secs.max(1);
+                    return Ok((ready_at - Utc::now().timestamp()).max(0));
+                }
+                let elapsed = Utc::now().timestamp() - user.last_placed;
+                let cooldown = self
+                    .rules
+                    .scaled_cooldown(self.rules.cooldown, user.streak.unwrap_or(0));
+                if elapsed < cooldown && self.in_grace(&user.address).await? {
+                    return Ok(0);
+                }
+                Ok((cooldown - elapsed).max(0))
+            }
+            Err(VpError::InvalidUser) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+    // unix seconds of the last change of the canvas, 0 if it never changed
+    pub async fn last_modified(&self, canvas_id: &str) -> Result<i64, VpError> {
+        self.canvas(canvas_id)?;
+        let rows = self.session.execute(&self.get_meta, (canvas_id,)).await?;
+        match rows.first_row_typed::<(Option<i64>,)>() {
+            Ok((last_update,)) => Ok(last_update.unwrap_or(0)),
+            Err(FirstRowTypedError::RowsEmpty) => Ok(0),
+            Err(e) => Err(VpError::ScyllaTypeErr(e)),
+        }
+    }
+    // bumps canvas_meta, placements skip it while this instance wrote it
+    // less than META_RESOLUTION_SECS ago. admin ops force it
+    // a failed write only logs, the pixels are already placed : )
+    async fn touch(&self, canvas_id: &str, force: bool) {
+        let Ok(canvas) = self.canvas(canvas_id) else {
+            return;
+        };
+        let now = Utc::now();
+        let secs = now.timestamp();
+        let last = canvas.touched.load(Ordering::Relaxed);
+        if !force && secs - last < META_RESOLUTION_SECS {
+            return;
+        }
+        canvas.touched.store(secs, Ordering::Relaxed);
+        let res = self
+            .session
+            .execute(&self.touch_meta, (now.timestamp_micros(), secs, canvas_id))
+            .await;
+        if let Err(e) = res {
+            canvas.touched.store(last, Ordering::Relaxed);
+            log::warn!("Unable to update canvas_meta of {} : {}", canvas_id, e);
+        }
+    }
+    // live feed of successful placements
+    // slow subscribers lag and drop the oldest updates (RecvError::Lagged)
+    pub fn subscribe(&self) -> broadcast::Receiver<PixelUpdate> {
+        self.updates.subscribe()
+    }
+    async fn publish(&self, canvas_id: &str, req: &UpdatePixel, address: &str) {
+        self.publish_update(PixelUpdate {
+            canvas_id: canvas_id.to_string(),
+            x: req.loc.x,
+            y: req.loc.y,
+            color: req.color,
+            address: address.to_string(),
+        })
+        .await
+    }
+    // the placement is already written, so nothing here fails it
+    async fn publish_update(&self, update: PixelUpdate) {
+        if let Some(sink) = &self.event_sink {
+            if let Err(e) = sink.publish(&update).await {
+                log::warn!("Placement event publish failed : {}", e);
+            }
+        }
+        // no subscribers is not an error : )
+        let _ = self.updates.send(update);
+    }
+    // partition of the current pixel op span, out of bounds pixels have none
+    fn record_partition(&self, canvas_id: &str, x: u32, y: u32) {
+        if let Ok(canvas) = self.canvas(canvas_id) {
+            if canvas.check_bounds(x, y).is_ok() {
+                Span::current().record("partition", canvas.partition_name(x, y));
+            }
+        }
+    }
+    #[tracing::instrument(
+        name = "update_db",
+        skip_all,
+        fields(canvas = canvas_id, x = req.loc.x, y = req.loc.y, partition = Empty, error = Empty)
+    )]
+    pub async fn update_db(&self, canvas_id: &str, req: &UpdatePixel) -> Result<(), VpError> {
+        let _write = self.writes.enter()?;
+        self.record_partition(canvas_id, req.loc.x, req.loc.y);
+        let fut = self
+            .write_pixel(canvas_id, req)
+            .map_err(VpError::pixel_write(req.loc.x, req.loc.y));
+        instrument(self.metrics, self.op_timeout, "update_db", fut).await
+    }
+    // the owner of a pixel sets it back to unpainted. it takes the cooldown and nonce like a placement,
+    // the history gets an ERASED_COLOR entry. req.color is ignored : )
+    pub async fn erase_pixel(&self, canvas_id: &str, req: &UpdatePixel) -> Result<(), VpError> {
+        let _write = self.writes.enter()?;
+        self.record_partition(canvas_id, req.loc.x, req.loc.y);
+        let fut = self
+            .write_erase(canvas_id, req)
+            .map_err(VpError::pixel_write(req.loc.x, req.loc.y));
+        instrument(self.metrics, self.op_timeout, "erase_pixel", fut).await
+    }
+    async fn write_erase(&self, canvas_id: &str, req: &UpdatePixel) -> Result<(), VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        let address = &req_address(req)?;
+        let (x, y) = (req.loc.x, req.loc.y);
+        canvas.check_bounds(x, y)?;
+        let last_placed = self.placed_at(req, address, Utc::now().timestamp())?;
+        let next = self
+            .check_cooldown(address, canvas.cooldown_at(x, y, self.rules.cooldown))
+            .await?;
+        if req.nonce != Some(next.nonce) {
+            return Err(VpError::InvalidNonce {
+                expected: next.nonce,
+            });
+        }
+        match self.fetch_pixel(canvas_id, x, y).await {
+            Ok(pixel) if pixel.address == *address => {}
+            Ok(_) | Err(VpError::NoPixelData) => return Err(VpError::NotPixelOwner),
+            Err(e) => return Err(e),
+        }
+        let nonce = i64::try_from(next.nonce + 1)?;
+        let part = canvas.partition_name(x, y);
+        let (ix, iy) = (i32::try_from(x)?, i32::try_from(y)?);
+        let erased = PixelData {
+            address: address.to_string(),
+            color: ERASED_COLOR,
+            last_placed,
+            tag: None,
+        };
+        // no stats / feed / player history, an erase isn't a placement
+        tokio::try_join!(
+            self.session.execute(
+                &self.insert_user,
+                (
+                    address,
+                    ix,
+                    iy,
+                    ERASED_COLOR,
+                    last_placed,
+                    next.streak,
+                    nonce
+                ),
+            ),
+            self.session
+                .execute(&self.delete_pixel_col, (part, ix, iy, iy)),
+            self.session
+                .execute(&self.insert_history, (part, ix, iy, last_placed, &erased)),
+        )?;
+        self.spend_ink(address, &next, 1).await?;
+        self.touch(canvas_id, false).await;
+        if let Some(cache) = &self.pixel_cache {
+            cache.del(part, x, y).await;
+        }
+        // subscribers just see the background color being placed
+        self.publish_update(PixelUpdate {
+            canvas_id: canvas_id.to_string(),
+            x,
+            y,
+            color: u32::try_from(self.empty_color(canvas))?,
+            address: address.to_string(),
+        })
+        .await;
+        Ok(())
+    }
+    // palette index unpainted cells are reported as
+    pub fn background_color(&self) -> i32 {
+        self.background_color
+    }
+    // color of unpainted cells of a canvas, the render background on truecolor ones
+    fn empty_color(&self, canvas: &Canvas) -> i32 {
+        match canvas.color_mode {
+            ColorMode::Palette(_) => self.background_color,
+            ColorMode::Truecolor => pack_rgb(self.render_background),
+        }
+    }
+    // dry run of update_db : same checks and errors, nothing is written
+    pub async fn validate_placement(
+        &self,
+        canvas_id: &str,
+        req: &UpdatePixel,
+    ) -> Result<(), VpError> {
+        self.check_placement(canvas_id, req).await.map(|_| ())
+    }
+    // every gate check_placement would stop at, each reported on its own. nothing is written
+    // gates needing the address (reserved, cooldown ..) are left out without one,
+    // the nonce one without a passed cooldown. rate limit / signature are the handler's : )
+    pub async fn explain_placement(
+        &self,
+        canvas_id: &str,
+        req: &UpdatePixel,
+    ) -> Result<PlacementDecision, VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        let mut decision = PlacementDecision::default();
+        decision.push("read_only", self.writes.enter().map(|_| ()));
+        let (x, y) = (req.loc.x, req.loc.y);
+        decision.push("bounds", canvas.check_bounds(x, y));
+        let color = i32::try_from(req.color).unwrap_or(i32::MAX);
+        decision.push(
+            "color",
+            canvas.color_mode.check_color(req.color).map(|_| ()),
+        );
+        decision.push("team", self.check_team(req));
+        decision.push("tag", self.check_tag(req));
+        decision.push("color_quota", self.check_color_quota(color, 1).await);
+        let address = match req_address(req) {
+            Ok(address) => address,
+            Err(e) => {
+                decision.push("address", Err(e));
+                return Ok(decision);
+            }
+        };
+        decision.push("address", Ok(()));
+        decision.push("reserved", self.check_reserved(canvas, x, y, &address));
+        let placed_at = self.placed_at(req, &address, Utc::now().timestamp());
+        decision.push("placed_at", placed_at.map(|_| ()));
+        let cooldown = canvas.cooldown_at(x, y, self.rules.cooldown);
+        let next = self.check_cooldown(&address, cooldown).await;
+        let nonce = next.as_ref().ok().map(|next| next.nonce);
+        decision.push("cooldown", next.map(|_| ()));
+        let daily_quota = self.check_daily_quota(&address, 1).await;
+        decision.push("daily_quota", daily_quota.map(|_| ()));
+        if let Some(nonce) = nonce {
+            let valid_nonce = if req.nonce == Some(nonce) {
+                Ok(())
+            } else {
+                Err(VpError::InvalidNonce { expected: nonce })
+            };
+            decision.push("nonce", valid_nonce);
+        }
+        Ok(decision)
+    }
+    // signed nonces must match the player row, so a signature can't be replayed
+    // concurrent placements with the same nonce can still race, like the cooldown
+    async fn check_placement(
+        &self,
+        canvas_id: &str,
+        req: &UpdatePixel,
+    ) -> Result<NextPlacement, VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        let address = &req_address(req)?;
+        canvas.check_bounds(req.loc.x, req.loc.y)?;
+        self.check_reserved(canvas, req.loc.x, req.loc.y, address)?;
+        self.check_team(req)?;
+        self.check_tag(req)?;
+        let color = canvas.color_mode.check_color(req.color)?;
+        self.placed_at(req, address, Utc::now().timestamp())?;
+        self.check_color_quota(color, 1).await?;
+        let cooldown = canvas.cooldown_at(req.loc.x, req.loc.y, self.rules.cooldown);
+        let mut next = self.check_cooldown(address, cooldown).await?;
+        next.daily_placed = self.check_daily_quota(address, 1).await?;
+        if req.nonce != Some(next.nonce) {
+            return Err(VpError::InvalidNonce {
+                expected: next.nonce,
+            });
+        }
+        Ok(next)
+    }
+    // idempotency keys : a placement whose key was seen in the window succeeds without writing.
+    // the key is read before validation, so a retry isn't rejected by the cooldown
+    // the first write started, and claimed (LWT) after it, so concurrent retries write once.
+    // a failed write releases its key again : )
+    async fn write_pixel(&self, canvas_id: &str, req: &UpdatePixel) -> Result<(), VpError> {
+        let address = req_address(req)?;
+        let key = req.idempotency_key.as_ref().map(|key| (&address, key));
+        if let Some(key) = key {
+            let rows = self.session.execute(&self.get_placement_key, key).await?;
+            if rows.rows_num().unwrap_or(0) > 0 {
+                return Ok(());
+            }
+        }
+        let next = self.check_placement(canvas_id, req).await?;
+        if let Some(key) = key {
+            let rows = self.session.execute(&self.claim_placement_key, key).await?;
+            if !lwt_applied(rows) {
+                return Ok(());
+            }
+        }
+        let res = self
+            .write_placement(canvas_id, req, next, Written::Nothing)
+            .await;
+        if let (Err(_), Some(key)) = (&res, key) {
+            if let Err(e) = self.session.execute(&self.delete_placement_key, key).await {
+                log::warn!("Unable to release idempotency key : {}", e);
+            }
+        }
+        res
+    }
+    // compare and set placement : only written when the pixel still has expected_prev_color
+    // (None : still unpainted). Ok(false) when someone else placed there first.
+    // LWTs take a paxos round, several times the latency of update_db, so keep it for
+    // contested pixels. idempotency keys are ignored here : )
+    pub async fn update_db_if(
+        &self,
+        canvas_id: &str,
+        req: &UpdatePixel,
+        expected_prev_color: Option<i32>,
+    ) -> Result<bool, VpError> {
+        let _write = self.writes.enter()?;
+        self.record_partition(canvas_id, req.loc.x, req.loc.y);
+        let fut = self
+            .write_pixel_if(canvas_id, req, expected_prev_color)
+            .map_err(VpError::pixel_write(req.loc.x, req.loc.y));
+        instrument(self.metrics, self.op_timeout, "update_db_if", fut).await
+    }
+    async fn write_pixel_if(
+        &self,
+        canvas_id: &str,
+        req: &UpdatePixel,
+        expected_prev_color: Option<i32>,
+    ) -> Result<bool, VpError> {
+        let next = self.check_placement(canvas_id, req).await?;
+        let canvas = self.canvas(canvas_id)?;
+        let address = &req_address(req)?;
+        let part = canvas.partition_name(req.loc.x, req.loc.y);
+        let (ix, iy) = (i32::try_from(req.loc.x)?, i32::try_from(req.loc.y)?);
+        // the cache may lag behind, the condition is checked against the row itself
+        let rows = self
+            .session
+            .execute(&self.get_pixel, (part, ix, iy))
+            .await?;
+        // the raw value is kept for the condition, a pixel from before tags has no tag
+        // field and wouldn't equal PixelData with a null one
+        let current = match rows
+            .maybe_first_row()
+            .map_err(|e| VpError::ScyllaTypeErr(FirstRowTypedError::RowsExpected(e)))?
+        {
+            Some(row) => {
+                let raw = row.columns.first().cloned().flatten();
+                let (pixel,) = row.into_typed::<(PixelData,)>()?;
+                Some((pixel, raw))
+            }
+            None => None,
+        };
+        if current.as_ref().map(|(pixel, _)| pixel.color) != expected_prev_color {
+            return Ok(false);
+        }
+        let last_placed = self.placed_at(req, address, Utc::now().timestamp())?;
+        let pixel = PixelData {
+            address: address.to_string(),
+            color: i32::try_from(req.color)?,
+            last_placed,
+            tag: req.tag.clone(),
+        };
+        // the row we read is the one swapped, a change since fails the condition
+        let rows = match &current {
+            Some((_, prev)) => {
+                self.session
+                    .execute(&self.update_pixel_if, (&pixel, part, ix, iy, prev))
+                    .await?
+            }
+            None => {
+                self.session
+                    .execute(&self.insert_pixel_if_empty, (part, ix, iy, &pixel))
+                    .await?
+            }
+        };
+        if !lwt_applied(rows) {
+            return Ok(false);
+        }
+        self.write_placement(canvas_id, req, next, Written::Canvas(last_placed))
+            .await?;
+        Ok(true)
+    }
+    // placement whose cooldown check and player write are one LWT, so two placements
+    // racing past check_cooldown can't both land. the loser gets CooldownActive.
+    // like update_db_if it pays a paxos round, idempotency keys are ignored : )
+    pub async fn place_atomic(&self, canvas_id: &str, req: &UpdatePixel) -> Result<(), VpError> {
+        let _write = self.writes.enter()?;
+        self.record_partition(canvas_id, req.loc.x, req.loc.y);
+        let fut = self
+            .write_atomic(canvas_id, req)
+            .map_err(VpError::pixel_write(req.loc.x, req.loc.y));
+        instrument(self.metrics, self.op_timeout, "place_atomic", fut).await
+    }
+    async fn write_atomic(&self, canvas_id: &str, req: &UpdatePixel) -> Result<(), VpError> {
+        let next = self.check_placement(canvas_id, req).await?;
+        let address = &req_address(req)?;
+        let (ix, iy) = (i32::try_from(req.loc.x)?, i32::try_from(req.loc.y)?);
+        let color = i32::try_from(req.color)?;
+        let now = Utc::now().timestamp();
+        let last_placed = self.placed_at(req, address, now)?;
+        let nonce = i64::try_from(next.nonce + 1)?;
+        // the player row is only moved forward when its last placement is old enough
+        let rows = self
+            .session
+            .execute(
+                &self.claim_player,
+                (
+                    ix,
+                    iy,
+                    color,
+                    last_placed,
+                    next.streak,
+                    nonce,
+                    address,
+                    now - next.cooldown_secs,
+                ),
+            )
+            .await?;
+        let prev_placed = lwt_column(&rows, "last_placed").and_then(|c| c.as_bigint());
+        let applied = match (lwt_applied(rows), prev_placed) {
+            (true, _) => true,
+            // no player row yet, first placement of the address
+            (false, None) => lwt_applied(
+                self.session
+                    .execute(
+                        &self.claim_new_player,
+                        (address, ix, iy, color, last_placed, next.streak, nonce),
+                    )
+                    .await?,
+            ),
+            (false, Some(_)) => false,
+        };
+        if !applied {
+            let elapsed = now - prev_placed.unwrap_or(now);
+            return Err(VpError::CooldownActive {
+                remaining_secs: (next.cooldown_secs - elapsed).max(1),
+            });
+        }
+        self.write_placement(canvas_id, req, next, Written::Player(last_placed))
+            .await
+    }
+    // written : row an LWT already wrote for this placement, it isn't written again
+    async fn write_placement(
+        &self,
+        canvas_id: &str,
+        req: &UpdatePixel,
+        next: NextPlacement,
+        written: Written,
+    ) -> Result<(), VpError> {
+        let nonce = i64::try_from(next.nonce + 1)?;
+        let canvas = self.canvas(canvas_id)?;
+        let (ix, iy) = (i32::try_from(req.loc.x)?, i32::try_from(req.loc.y)?);
+        let color = i32::try_from(req.color)?;
+        //already checked in check_placement
+        let address = &req_address(req)?;
+        let last_placed = match written {
+            Written::Canvas(last_placed) | Written::Player(last_placed) => last_placed,
+            Written::Nothing => self.placed_at(req, address, Utc::now().timestamp())?,
+        };
+
+        // player, canvas and history inserts are idempotent (same key, same values)
+        // so they are safe to retry. the stats counter is not, it is never retried.
+        // add user update
+        let user_update = async {
+            if let Written::Player(_) = written {
+                return Ok(());
+            }
+            self.retry
+                .run(|| {
+                    self.session
+                        .execute(
+                            &self.insert_user,
+                            (address, ix, iy, color, last_placed, next.streak, nonce),
+                        )
+                        .map_err(VpError::from)
+                })
+                .await
+                .map(|_| ())
+        };
+        // the player row remembers the last team it placed for
+        let team_update = async {
+            let Some(team) = &req.team else {
+                return Ok(());
+            };
+            tokio::try_join!(
+                self.session.execute(&self.set_player_team, (team, address)),
+                self.session.execute(&self.incr_team, (Counter(1), team))
+            )?;
+            Ok(())
+        };
+
+        // add  pixel update
+        let part = canvas.partition_name(req.loc.x, req.loc.y);
+        let pixel_data = PixelData {
+            address: address.to_string(),
+            color,
+            last_placed,
+            tag: req.tag.clone(),
+        };
+        let pixel_cache_data = pixel_data.clone();
+        let history_update = self.retry.run(|| {
+            self.session
+                .execute(
+                    &self.insert_history,
+                    (part, ix, iy, last_placed, pixel_data.clone()),
+                )
+                .map_err(VpError::from)
+        });
+        let pixel_update = async {
+            if let Written::Canvas(_) = written {
+                return Ok(());
+            }
+            self.retry
+                .run(|| {
+                    self.session
+                        .execute(&self.insert_pixel, (part, ix, iy, pixel_data.clone()))
+                        .map_err(VpError::from)
+                })
+                .await
+                .map(|_| ())
+        };
+        let player_history_update = self.retry.run(|| {
+            self.session
+                .execute(
+                    &self.insert_player_history,
+                    (address, last_placed, ix, iy, color),
+                )
+                .map_err(VpError::from)
+        });
+        let recent_update = self.retry.run(|| {
+            self.session
+                .execute(
+                    &self.insert_recent,
+                    (
+                        feed_bucket(last_placed),
+                        last_placed,
+                        address,
+                        ix,
+                        iy,
+                        color,
+                        canvas_id,
+                    ),
+                )
+                .map_err(VpError::from)
+        });
+        let index_update = self.retry.run(|| {
+            self.session
+                .execute(&self.insert_user_pixel, (address, part, ix, iy))
+                .map_err(VpError::from)
+        });
+        // counters can't share a batch with regular writes, separate execute
+        let stats_update = self
+            .session
+            .execute(&self.incr_stats, (Counter(1), address))
+            .map_err(VpError::from);
+        let global_update = self
+            .session
+            .execute(&self.incr_global, (Counter(1), global_shard()))
+            .map_err(VpError::from);
+        let pindex = canvas.partition_index(req.loc.x, req.loc.y);
+        let cells_update = self
+            .session
+            .execute(&self.incr_part_cells, (Counter(1), part))
+            .map_err(VpError::from);
+        let quota_update = self.incr_color_quota(color, 1);
+        tokio::try_join!(
+            user_update,
+            pixel_update,
+            history_update,
+            player_history_update,
+            recent_update,
+            index_update,
+            stats_update,
+            global_update,
+            cells_update,
+            quota_update,
+            team_update,
+            self.spend_ink(address, &next, 1),
+            self.count_daily(address, &next, 1)
+        )?;
+        self.count_cells(canvas, pindex, 1);
+        self.touch(canvas_id, false).await;
+        // keep cached reads fresh : )
+        if let Some(cache) = &self.pixel_cache {
+            cache
+                .set(part, req.loc.x, req.loc.y, &pixel_cache_data)
+                .await;
+        }
+        self.publish(canvas_id, req, address).await;
+        Ok(())
+    }
+    // idempotency keys are only honoured by update_db, batches are written as is
+    pub async fn update_db_batch(
+        &self,
+        canvas_id: &str,
+        reqs: &[UpdatePixel],
+    ) -> Result<(), VpError> {
+        let _write = self.writes.enter()?;
+        let canvas = self.canvas(canvas_id)?;
+        if reqs.len() > MAX_BATCH_PIXELS {
+            return Err(VpError::PixelBatchTooLarge);
+        }
+        // validate the whole slice before writing anything : )
+        let now = Utc::now().timestamp();
+        let normalized = reqs
+            .iter()
+            .map(req_address)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut pixels = Vec::with_capacity(reqs.len());
+        for (req, address) in reqs.iter().zip(&normalized) {
+            canvas.check_bounds(req.loc.x, req.loc.y)?;
+            let (ix, iy) = (i32::try_from(req.loc.x)?, i32::try_from(req.loc.y)?);
+            self.check_reserved(canvas, req.loc.x, req.loc.y, address)?;
+            self.check_team(req)?;
+            self.check_tag(req)?;
+            canvas.color_mode.check_color(req.color)?;
+            let last_placed = self.placed_at(req, address, now)?;
+            pixels.push((req, address, ix, iy, last_placed));
+        }
+        let mut colors: HashMap<i32, i64> = HashMap::new();
+        for req in reqs {
+            *colors.entry(i32::try_from(req.color)?).or_default() += 1;
+        }
+        try_join_all(
+            colors
+                .iter()
+                .map(|(color, count)| self.check_color_quota(*color, *count)),
+        )
+        .await?;
+        // an address placing in several regions waits for the slowest of them
+        let mut cooldowns: HashMap<&String, i64> = HashMap::new();
+        for (req, address, ..) in &pixels {
+            let cooldown = canvas.cooldown_at(req.loc.x, req.loc.y, self.rules.cooldown);
+            cooldowns
+                .entry(address)
+                .and_modify(|c| *c = (*c).max(cooldown))
+                .or_insert(cooldown);
+        }
+        let nexts = try_join_all(cooldowns.iter().map(|(address, cooldown)| async move {
+            let next = self.check_cooldown(address, *cooldown).await?;
+            Ok::<_, VpError>((*address, next))
+        }))
+        .await?;
+        let mut nexts: HashMap<&String, NextPlacement> = nexts.into_iter().collect();
+        let mut counts: HashMap<&String, i64> = HashMap::new();
+        for (_, address, ..) in &pixels {
+            *counts.entry(address).or_default() += 1;
+        }
+        for (address, count) in &counts {
+            self.rules
+                .check_stamp(address, *count, cooldowns[address])?;
+        }
+        // check_cooldown only checked the ink of a single placement
+        if let Some(ink) = self.rules.ink {
+            for (address, count) in &counts {
+                if let Some((available, _)) = nexts[address].ink {
+                    let required = ink.cost.saturating_mul(*count);
+                    if available < required {
+                        return Err(VpError::InsufficientInk {
+                            available,
+                            required,
+                        });
+                    }
+                }
+            }
+        }
+        let daily = try_join_all(counts.iter().map(|(address, count)| async move {
+            let placed = self.check_daily_quota(address, *count).await?;
+            Ok::<_, VpError>((*address, placed))
+        }))
+        .await?;
+        for (address, placed) in daily {
+            if let Some(next) = nexts.get_mut(address) {
+                next.daily_placed = placed;
+            }
+        }
+        // pixels of an address are signed with consecutive nonces, in slice order
+        let mut expected: HashMap<&String, u64> = nexts
+            .iter()
+            .map(|(address, next)| (*address, next.nonce))
+            .collect();
+        for (req, address, ..) in &pixels {
+            let nonce = expected.entry(address).or_default();
+            if req.nonce != Some(*nonce) {
+                return Err(VpError::InvalidNonce { expected: *nonce });
+            }
+            *nonce += 1;
+        }
+
+        // group pixel inserts by canvas_part so each batch stays in a single partition
+        let mut part_values: HashMap<usize, Vec<(&str, i32, i32, PixelData)>> = HashMap::new();
+        // player row only keeps the last placement of each address in the slice
+        let mut last_user: HashMap<&String, (i32, i32, i32, i64)> = HashMap::new();
+        let mut placed: HashMap<&String, i64> = HashMap::new();
+        let mut team_placed: HashMap<&String, i64> = HashMap::new();
+        let mut last_team: HashMap<&String, &String> = HashMap::new();
+        let mut cache_values = Vec::new();
+        let mut index_values = Vec::new();
+        let mut player_history_values = Vec::new();
+        for (req, address, ix, iy, last_placed) in pixels {
+            let color = i32::try_from(req.color)?;
+            let pindex = canvas.partition_index(req.loc.x, req.loc.y);
+            let part = canvas.canvas_part[pindex].as_str();
+            let pixel_data = PixelData {
+                address: address.to_string(),
+                color,
+                last_placed,
+                tag: req.tag.clone(),
+            };
+            if self.pixel_cache.is_some() {
+                cache_values.push((part, req.loc.x, req.loc.y, pixel_data.clone()));
+            }
+            part_values
+                .entry(pindex)
+                .or_default()
+                .push((part, ix, iy, pixel_data));
+            index_values.push((address, part, ix, iy));
+            player_history_values.push((address, last_placed, ix, iy, color));
+            last_user.insert(address, (ix, iy, color, last_placed));
+            *placed.entry(address).or_default() += 1;
+            if let Some(team) = &req.team {
+                *team_placed.entry(team).or_default() += 1;
+                last_team.insert(address, team);
+            }
+        }
+
+        let part_cells = part_values
+            .iter()
+            .map(|(pindex, values)| Ok((*pindex, i64::try_from(values.len())?)))
+            .collect::<Result<Vec<_>, VpError>>()?;
+        // a stamp over one partition is still split, big batches get warned / rejected by scylla
+        let pixel_chunks = part_values
+            .values()
+            .flat_map(|values| values.chunks(IMPORT_BATCH_SIZE))
+            .collect::<Vec<_>>();
+        let pixel_updates = try_join_all(pixel_chunks.into_iter().map(|values| async move {
+            let history_values: Vec<_> = values
+                .iter()
+                .map(|(part, ix, iy, pixel)| (*part, *ix, *iy, pixel.last_placed, pixel.clone()))
+                .collect();
+            let mut batch = Batch::new(BatchType::Unlogged);
+            let mut history_batch = Batch::new(BatchType::Unlogged);
+            values.iter().for_each(|_| {
+                batch.append_statement(self.insert_pixel.clone());
+                history_batch.append_statement(self.insert_history.clone());
+            });
+            tokio::try_join!(
+                self.session.batch(&batch, values),
+                self.session.batch(&history_batch, history_values)
+            )
+        }));
+        // every pixel of the slice counts as a placement of the streak
+        let user_updates = try_join_all(last_user.into_iter().map(
+            |(address, (ix, iy, color, last_placed))| {
+                let streak = match self.rules.cooldown_scaling {
+                    Some(_) => {
+                        let extra = i32::try_from(placed[address] - 1).unwrap_or(i32::MAX);
+                        nexts[address].streak.saturating_add(extra)
+                    }
+                    None => 0,
+                };
+                let nonce = i64::try_from(expected[address]).unwrap_or(i64::MAX);
+                self.session.execute(
+                    &self.insert_user,
+                    (address, ix, iy, color, last_placed, streak, nonce),
+                )
+            },
+        ));
+        let stats_updates = try_join_all(placed.into_iter().map(|(address, count)| {
+            self.session
+                .execute(&self.incr_stats, (Counter(count), address))
+        }));
+        let team_updates = try_join_all(team_placed.into_iter().map(|(team, count)| {
+            self.session
+                .execute(&self.incr_team, (Counter(count), team))
+        }));
+        let player_team_updates =
+            try_join_all(last_team.into_iter().map(|(address, team)| {
+                self.session.execute(&self.set_player_team, (team, address))
+            }));
+        let global_update = self.session.execute(
+            &self.incr_global,
+            (Counter(i64::try_from(reqs.len())?), global_shard()),
+        );
+        let cells_updates = try_join_all(part_cells.iter().map(|(pindex, count)| {
+            self.session.execute(
+                &self.incr_part_cells,
+                (Counter(*count), &canvas.canvas_part[*pindex]),
+            )
+        }));
+        let quota_updates = try_join_all(
+            colors
+                .iter()
+                .map(|(color, count)| self.incr_color_quota(*color, *count)),
+        );
+        let index_updates = try_join_all(
+            index_values
+                .iter()
+                .map(|values| self.session.execute(&self.insert_user_pixel, values)),
+        );
+        let player_history_updates = try_join_all(
+            player_history_values
+                .iter()
+                .map(|values| self.session.execute(&self.insert_player_history, values)),
+        );
+        let recent_updates = try_join_all(player_history_values.iter().map(
+            |(address, last_placed, ix, iy, color)| {
+                self.session.execute(
+                    &self.insert_recent,
+                    (
+                        feed_bucket(*last_placed),
+                        last_placed,
+                        address,
+                        ix,
+                        iy,
+                        color,
+                        canvas_id,
+                    ),
+                )
+            },
+        ));
+        tokio::try_join!(
+            user_updates,
+            pixel_updates,
+            index_updates,
+            player_history_updates,
+            recent_updates,
+            stats_updates,
+            team_updates,
+            player_team_updates,
+            global_update,
+            cells_updates
+        )?;
+        // already VpError futures, so not part of the join above
+        quota_updates.await?;
+        try_join_all(
+            counts
+                .iter()
+                .map(|(address, count)| self.spend_ink(address, &nexts[address], *count)),
+        )
+        .await?;
+        try_join_all(
+            counts
+                .iter()
+                .map(|(address, count)| self.count_daily(address, &nexts[address], *count)),
+        )
+        .await?;
+        for (pindex, count) in part_cells {
+            self.count_cells(canvas, pindex, count);
+        }
+        self.touch(canvas_id, false).await;
+        if let Some(cache) = &self.pixel_cache {
+            let cached = cache_values
+                .iter()
+                .map(|(part, x, y, pixel)| cache.set(part, *x, *y, pixel));
+            futures::future::join_all(cached).await;
+        }
+        let published = reqs
+            .iter()
+            .zip(&normalized)
+            .map(|(req, address)| self.publish(canvas_id, req, address));
+        futures::future::join_all(published).await;
+        Ok(())
+    }
+    #[tracing::instrument(
+        name = "get_pixel",
+        skip_all,
+        fields(canvas = canvas_id, x = x, y = y, partition = Empty, error = Empty)
+    )]
+    pub async fn get_pixel(&self, canvas_id: &str, x: u32, y: u32) -> Result<PixelData, VpError> {
+        self.record_partition(canvas_id, x, y);
+        let fut = self
+            .fetch_pixel(canvas_id, x, y)
+            .map_err(VpError::pixel_read(x, y));
+        instrument(self.metrics, self.op_timeout, "get_pixel", fut).await
+    }
+    // unpainted pixels come back as default_color with no owner : )
+    pub async fn get_pixel_or_default(
+        &self,
+        canvas_id: &str,
+        x: u32,
+        y: u32,
+        default_color: i32,
+    ) -> Result<PixelData, VpError> {
+        match self.get_pixel(canvas_id, x, y).await {
+            Err(VpError::NoPixelData) => Ok(PixelData {
+                address: String::new(),
+                color: default_color,
+                last_placed: 0,
+                tag: None,
+            }),
+            res => res,
+        }
+    }
+    // rgb of the pixel, so thin clients can skip the palette
+    // unpainted pixels and indexes outside palette are the render background, like in render_png
+    pub async fn get_pixel_rgb(
+        &self,
+        canvas_id: &str,
+        x: u32,
+        y: u32,
+        palette: &Palette,
+    ) -> Result<[u8; 3], VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        let color = match self.get_pixel(canvas_id, x, y).await {
+            Ok(pixel) => pixel.color,
+            Err(VpError::NoPixelData) => return Ok(self.render_background),
+            Err(e) => return Err(e),
+        };
+        Ok(match canvas.color_mode {
+            ColorMode::Palette(_) => usize::try_from(color)
+                .ok()
+                .and_then(|c| palette.colors.get(c))
+                .copied()
+                .unwrap_or(self.render_background),
+            ColorMode::Truecolor => unpack_rgb(color),
+        })
+    }
+    async fn fetch_pixel(&self, canvas_id: &str, x: u32, y: u32) -> Result<PixelData, VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        canvas.check_bounds(x, y)?;
+        let part = canvas.partition_name(x, y);
+        if let Some(cache) = &self.pixel_cache {
+            if let Some(pixel) = cache.get(part, x, y).await {
+                return Ok(pixel);
+            }
+        }
+        let ix = i32::try_from(x)?;
+        let iy = i32::try_from(y)?;
+        let rows = self
+            .retry
+            .run(|| {
+                self.session
+                    .execute(&self.get_pixel, (part, ix, iy))
+                    .map_err(VpError::from)
+            })
+            .await?;
+        let res = rows.first_row_typed::<(PixelData,)>();
+        match res {
+            Ok((pixel,)) => {
+                if let Some(cache) = &self.pixel_cache {
+                    cache.set(part, x, y, &pixel).await;
+                }
+                Ok(pixel)
+            }
+            Err(FirstRowTypedError::RowsEmpty) => Err(VpError::NoPixelData),
+            Err(e) => Err(VpError::ScyllaTypeErr(e)),
+        }
+    }
+    // every painted pixel of the canvas, partition by partition
+    // rows are paged from scylla as the stream is polled, nothing is buffered : )
+    // page_size None is the builder's with_page_size
+    pub fn stream_canvas(
+        &self,
+        canvas_id: &str,
+        page_size: Option<i32>,
+    ) -> Result<impl Stream<Item = Result<(u32, u32, PixelData), VpError>>, VpError> {
+        let parts = self.canvas(canvas_id)?.canvas_part.clone();
+        let session = self.session.clone();
+        let mut scan = self.scan_part.clone();
+        set_paging(&mut scan, page_size, self.page_size);
+        let pixels = futures::stream::iter(parts)
+            .map(move |part| scan_partition(session.clone(), scan.clone(), part))
+            .flatten();
+        Ok(pixels)
+    }
+    // every pixel of a single canvas_part (any canvas), page_size rows per page
+    // for looking at a hot partition or checking a migration
+    pub fn dump_partition(
+        &self,
+        part: &str,
+        page_size: i32,
+    ) -> Result<impl Stream<Item = Result<(u32, u32, PixelData), VpError>>, VpError> {
+        let known = self
+            .canvases
+            .values()
+            .any(|canvas| canvas.canvas_part.iter().any(|p| p == part));
+        if !known {
+            return Err(VpError::UnknownPartition(part.to_string()));
+        }
+        let mut scan = self.scan_part.clone();
+        set_paging(&mut scan, Some(page_size), self.page_size);
+        Ok(scan_partition(self.session.clone(), scan, part.to_string()))
+    }
+    pub fn palette(&self, canvas_id: &str) -> Result<&Palette, VpError> {
+        match &self.canvas(canvas_id)?.color_mode {
+            ColorMode::Palette(palette) => Ok(palette),
+            ColorMode::Truecolor => Err(VpError::NoPalette(canvas_id.to_string())),
+        }
+    }
+    // canvas_part names of the canvas and the cells each one holds, no db call
+    pub fn partitions(&self, canvas_id: &str) -> Result<Vec<PartitionInfo>, VpError> {
+        Ok(self.canvas(canvas_id)?.partition_infos())
+    }
+    // everything a client needs to draw the canvas, builder time state only : )
+    pub fn canvas_info(&self, canvas_id: &str) -> Result<CanvasInfo, VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        Ok(CanvasInfo {
+            dim: canvas.canvas_dim,
+            palette: match &canvas.color_mode {
+                ColorMode::Palette(palette) => palette.entries(),
+                ColorMode::Truecolor => Vec::new(),
+            },
+            truecolor: matches!(canvas.color_mode, ColorMode::Truecolor),
+            cooldown_secs: self.rules.cooldown,
+            ink: self.rules.ink,
+            daily_quota: (self.daily_quota > 0).then_some(self.daily_quota),
+            background_color: self.background_color,
+            reserved_regions: canvas.reserved_regions.clone(),
+            region_cooldowns: canvas.region_cooldowns.clone(),
+            partitions: canvas.canvas_part.clone(),
+        })
+    }
+    // canvas_dim x canvas_dim png, pixel (x,y) is column x of row y
+    // the rgb buffer is filled straight from the canvas stream, so it's allocated once : )
+    // options.scale upscales it, options.watermark is captioned in the bottom right corner
+    // palette is ignored on truecolor canvases
+    pub async fn render_png(
+        &self,
+        canvas_id: &str,
+        palette: &Palette,
+        options: &RenderOptions,
+    ) -> Result<Vec<u8>, VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        let canvas_dim = canvas.canvas_dim;
+        let dim = canvas_dim as usize;
+        let mut rgb = self.render_background.repeat(dim * dim);
+        let mut pixels = Box::pin(self.stream_canvas(canvas_id, None)?);
+        while let Some((x, y, pixel)) = pixels.try_next().await? {
+            let (x, y) = (x as usize, y as usize);
+            if x >= dim || y >= dim {
+                continue;
+            }
+            let color = match canvas.color_mode {
+                ColorMode::Palette(_) => usize::try_from(pixel.color)
+                    .ok()
+                    .and_then(|c| palette.colors.get(c))
+                    .copied()
+                    .unwrap_or(self.render_background),
+                ColorMode::Truecolor => unpack_rgb(pixel.color),
+            };
+            let offset = (y * dim + x) * 3;
+            rgb[offset..offset + 3].copy_from_slice(&color);
+        }
+        let scale = options
+            .scale
+            .clamp(1, (MAX_RENDER_DIM / canvas_dim.max(1)).max(1));
+        let out_dim = canvas_dim * scale;
+        let mut rgb = upscale(&rgb, dim, scale as usize);
+        if let Some(watermark) = &options.watermark {
+            let out = out_dim as usize;
+            stamp_text(&mut rgb, out, out, watermark, (scale as usize / 2).max(1));
+        }
+        let mut png = Vec::new();
+        PngEncoder::new(&mut png).write_image(&rgb, out_dim, out_dim, ColorType::Rgb8)?;
+        Ok(png)
+    }
+    // the region's history replayed as a looping gif, pixel (x,y) of a frame is
+    // column x - x0 of row y - y0. frame i shows every placement before
+    // first + (i + 1) * frame_interval_secs, so the last one is the region as it is now.
+    // erased and off palette cells are the background, palette is ignored on truecolor canvases
+    pub async fn render_timelapse_gif(
+        &self,
+        canvas_id: &str,
+        region: Rect,
+        frame_interval_secs: i64,
+        palette: &Palette,
+    ) -> Result<Vec<u8>, VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        let Rect { x0, y0, x1, y1 } = region;
+        // the fields are pub, so the corners may not be the ones Rect::new orders
+        canvas.check_region(x0, y0, x1, y1)?;
+        let (width, height) = (x1 - x0 + 1, y1 - y0 + 1);
+        if region.area() > MAX_TIMELAPSE_AREA {
+            return Err(VpError::TimelapseTooLarge);
+        }
+        let interval = frame_interval_secs.max(1);
+        let mut cols = Vec::new();
+        for (pindex, xr, yr) in canvas.split_region(x0..=x1, y0..=y1) {
+            let (iy0, iy1) = (i32::try_from(*yr.start())?, i32::try_from(*yr.end())?);
+            for x in xr {
+                cols.push((&canvas.canvas_part[pindex], x, i32::try_from(x)?, iy0, iy1));
+            }
+        }
+        let mut placements: Vec<(i64, u32, u32, i32)> = futures::stream::iter(cols)
+            .map(|(part, x, ix, iy0, iy1)| async move {
+                let mut placements = Vec::new();
+                let mut rows = self
+                    .session
+                    .execute_iter(self.get_history_col.clone(), (part, ix, iy0, iy1))
+                    .await?
+                    .into_typed::<(i32, i64, PixelData)>();
+                while let Some(row) = rows.next().await {
+                    let (iy, last_placed, pixel) = row?;
+                    placements.push((last_placed, x, u32::try_from(iy)?, pixel.color));
+                }
+                Ok::<_, VpError>(placements)
+            })
+            .buffer_unordered(REGION_CONCURRENCY)
+            .try_concat()
+            .await?;
+        placements.sort_unstable_by_key(|(last_placed, x, y, _)| (*last_placed, *x, *y));
+        let first = placements.first().map_or(0, |(ts, ..)| *ts);
+        let last = placements.last().map_or(0, |(ts, ..)| *ts);
+        let frames = (last - first) / interval + 1;
+        if frames > MAX_TIMELAPSE_FRAMES {
+            return Err(VpError::TimelapseTooLarge);
+        }
+        let [br, bg, bb] = self.render_background;
+        let background = [br, bg, bb, u8::MAX];
+        let mut rgba = RgbaImage::from_pixel(width, height, background.into());
+        let mut gif = Vec::new();
+        {
+            let mut encoder = GifEncoder::new_with_speed(&mut gif, 10);
+            encoder.set_repeat(Repeat::Infinite)?;
+            let mut placements = placements.into_iter().peekable();
+            for frame in 0..frames {
+                let end = first + (frame + 1) * interval;
+                while let Some((_, x, y, color)) =
+                    placements.next_if(|(last_placed, ..)| *last_placed < end)
+                {
+                    let [r, g, b] = match canvas.color_mode {
+                        ColorMode::Palette(_) => usize::try_from(color)
+                            .ok()
+                            .and_then(|c| palette.colors.get(c))
+                            .copied()
+                            .unwrap_or(self.render_background),
+                        ColorMode::Truecolor if color == ERASED_COLOR => self.render_background,
+                        ColorMode::Truecolor => unpack_rgb(color),
+                    };
+                    rgba.put_pixel(x - x0, y - y0, [r, g, b, u8::MAX].into());
+                }
+                encoder.encode_frame(Frame::from_parts(
+                    rgba.clone(),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(TIMELAPSE_FRAME_MS, 1),
+                ))?;
+            }
+        }
+        Ok(gif)
+    }
+    // zoom levels of the canvas as color bytes, cell (x,y) at x * dim + y like packed regions
+    // (3 bytes per cell on truecolor canvases, where palette is ignored).
+    // level 0 is full res, each next one halves the side (rounded up) by majority color.
+    // stops early once a level is a single cell, unpainted and off palette cells are the background
+    pub async fn render_mipmap(
+        &self,
+        canvas_id: &str,
+        levels: u32,
+        palette: &Palette,
+    ) -> Result<Vec<Vec<u8>>, VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        let mode = &canvas.color_mode;
+        let mut dim = canvas.canvas_dim as usize;
+        let mut cells = vec![self.empty_color(canvas); dim * dim];
+        let mut pixels = Box::pin(self.stream_canvas(canvas_id, None)?);
+        while let Some((x, y, pixel)) = pixels.try_next().await? {
+            let (x, y) = (x as usize, y as usize);
+            let valid = match mode {
+                ColorMode::Palette(_) => palette.contains(pixel.color),
+                ColorMode::Truecolor => mode.contains(pixel.color),
+            };
+            if x >= dim || y >= dim || !valid {
+                continue;
+            }
+            cells[x * dim + y] = pixel.color;
+        }
+        let mut mips = vec![cells];
+        while mips.len() < levels.max(1) as usize && dim > 1 {
+            let next = downsample_majority(&mips[mips.len() - 1], dim);
+            dim = dim.div_ceil(2);
+            mips.push(next);
+        }
+        let n = mode.cell_bytes();
+        Ok(mips
+            .iter()
+            .map(|mip| {
+                let mut bytes = vec![0; mip.len() * n];
+                for (cell, color) in bytes.chunks_exact_mut(n).zip(mip) {
+                    mode.encode(*color, cell);
+                }
+                bytes
+            })
+            .collect())
+    }
+    // ADMIN ONLY : seeds the canvas from a canvas_dim x canvas_dim png
+    // every cell is written with the nearest palette color (its exact rgb on truecolor canvases),
+    // attributed to address
+    // only the canvas table is touched, no history / cooldown / stats : )
+    pub async fn import_png(
+        &self,
+        canvas_id: &str,
+        bytes: &[u8],
+        palette: &Palette,
+        address: &str,
+    ) -> Result<u64, VpError> {
+        let _write = self.writes.enter()?;
+        let canvas = self.canvas(canvas_id)?;
+        let address = &normalize_address(address)?;
+        let img = image::load_from_memory_with_format(bytes, ImageFormat::Png)?.to_rgb8();
+        if img.width() != canvas.canvas_dim || img.height() != canvas.canvas_dim {
+            return Err(VpError::DimensionMismatch {
+                width: img.width(),
+                height: img.height(),
+            });
+        }
+        let last_placed = Utc::now().timestamp();
+        let mut part_values: HashMap<usize, Vec<(u32, u32, PixelData)>> = HashMap::new();
+        for (x, y, rgb) in img.enumerate_pixels() {
+            let color = match canvas.color_mode {
+                ColorMode::Palette(_) => {
+                    palette.nearest(rgb.0).ok_or(VpError::ColorSizeMismatch)?
+                }
+                ColorMode::Truecolor => pack_rgb(rgb.0),
+            };
+            part_values
+                .entry(canvas.partition_index(x, y))
+                .or_default()
+                .push((
+                    x,
+                    y,
+                    PixelData {
+                        address: address.to_string(),
+                        color,
+                        last_placed,
+                        tag: None,
+                    },
+                ));
+        }
+        // one unlogged batch per chunk, every chunk stays in a single partition
+        let mut batches = Vec::new();
+        for (pindex, values) in &part_values {
+            for chunk in values.chunks(IMPORT_BATCH_SIZE) {
+                let values = chunk
+                    .iter()
+                    .map(|(x, y, pixel)| {
+                        Ok((
+                            canvas.canvas_part[*pindex].as_str(),
+                            i32::try_from(*x)?,
+                            i32::try_from(*y)?,
+                            pixel,
+                        ))
+                    })
+                    .collect::<Result<Vec<_>, VpError>>()?;
+                let mut batch = Batch::new(BatchType::Unlogged);
+                values
+                    .iter()
+                    .for_each(|_| batch.append_statement(self.insert_pixel.clone()));
+                batches.push((batch, values));
+            }
+        }
+        futures::stream::iter(batches)
+            .map(|(batch, values)| async move {
+                self.session
+                    .batch(&batch, values)
+                    .map_err(VpError::from)
+                    .await
+            })
+            .buffer_unordered(REGION_CONCURRENCY)
+            .try_collect::<Vec<_>>()
+            .await?;
+        if let Some(cache) = &self.pixel_cache {
+            let cached = part_values.iter().flat_map(|(pindex, values)| {
+                values
+                    .iter()
+                    .map(|(x, y, pixel)| cache.set(&canvas.canvas_part[*pindex], *x, *y, pixel))
+            });
+            futures::future::join_all(cached).await;
+        }
+        self.touch(canvas_id, true).await;
+        Ok(part_values.values().map(|v| v.len() as u64).sum())
+    }
+    // DEV ONLY : count random pixels of random addresses, for load tests
+    // same seed, same canvas. only the canvas table is written, like import_png : )
+    #[cfg(feature = "seed")]
+    pub async fn seed_random(
+        &self,
+        canvas_id: &str,
+        count: u64,
+        palette: &Palette,
+        seed: u64,
+    ) -> Result<(), VpError> {
+        let _write = self.writes.enter()?;
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        let canvas = self.canvas(canvas_id)?;
+        let colors = i32::try_from(palette.colors.len())?;
+        if colors == 0 {
+            return Err(VpError::ColorSizeMismatch);
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
+        let last_placed = Utc::now().timestamp();
+        let mut remaining = count;
+        // generated in rounds so a huge count doesn't sit in memory
+        while remaining > 0 {
+            let round = remaining.min(MAX_REGION_AREA);
+            remaining -= round;
+            let mut part_values: HashMap<usize, Vec<(i32, i32, PixelData)>> = HashMap::new();
+            for _ in 0..round {
+                let (x, y) = (
+                    rng.gen_range(0..canvas.canvas_dim),
+                    rng.gen_range(0..canvas.canvas_dim),
+                );
+                let pixel = PixelData {
+                    address: format!("0x{}", hex::encode(rng.gen::<[u8; 20]>())),
+                    color: rng.gen_range(0..colors),
+                    last_placed,
+                    tag: None,
+                };
+                part_values
+                    .entry(canvas.partition_index(x, y))
+                    .or_default()
+                    .push((i32::try_from(x)?, i32::try_from(y)?, pixel));
+            }
+            let mut batches = Vec::new();
+            for (pindex, values) in &part_values {
+                let part = canvas.canvas_part[*pindex].as_str();
+                for chunk in values.chunks(IMPORT_BATCH_SIZE) {
+                    let mut batch = Batch::new(BatchType::Unlogged);
+                    chunk
+                        .iter()
+                        .for_each(|_| batch.append_statement(self.insert_pixel.clone()));
+                    let values: Vec<_> = chunk
+                        .iter()
+                        .map(|(x, y, pixel)| (part, x, y, pixel))
+                        .collect();
+                    batches.push((batch, values));
+                }
+            }
+            futures::stream::iter(batches)
+                .map(|(batch, values)| async move {
+                    self.session
+                        .batch(&batch, values)
+                        .map_err(VpError::from)
+                        .await
+                })
+                .buffer_unordered(REGION_CONCURRENCY)
+                .try_collect::<Vec<_>>()
+                .await?;
+            // cached pixels would hide the seeded ones
+            if let Some(cache) = &self.pixel_cache {
+                let cached = part_values.iter().flat_map(|(pindex, values)| {
+                    values.iter().map(|(x, y, _)| {
+                        cache.del(&canvas.canvas_part[*pindex], *x as u32, *y as u32)
+                    })
+                });
+                futures::future::join_all(cached).await;
+            }
+        }
+        self.touch(canvas_id, true).await;
+        Ok(())
+    }
+    // placement count per bucket_size x bucket_size block, from the history table
+    // since filters on last_placed client side (not a partition prefix), the scan is paged
+    pub async fn placement_heatmap(
+        &self,
+        canvas_id: &str,
+        bucket_size: u32,
+        since: Option<i64>,
+    ) -> Result<Vec<(u32, u32, u64)>, VpError> {
+        let bucket_size = bucket_size.max(1);
+        let mut buckets: HashMap<(u32, u32), u64> = HashMap::new();
+        for part in &self.canvas(canvas_id)?.canvas_part {
+            let mut rows = self
+                .session
+                .execute_iter(self.scan_history.clone(), (part,))
+                .await?
+                .into_typed::<(i32, i32, i64)>();
+            while let Some(row) = rows.next().await {
+                let (x, y, last_placed) = row?;
+                if since.is_some_and(|since| last_placed < since) {
+                    continue;
+                }
+                let (x, y) = (u32::try_from(x)?, u32::try_from(y)?);
+                *buckets
+                    .entry((x / bucket_size, y / bucket_size))
+                    .or_default() += 1;
+            }
+        }
+        let mut heatmap: Vec<_> = buckets
+            .into_iter()
+            .map(|((bx, by), count)| (bx, by, count))
+            .collect();
+        heatmap.sort_unstable();
+        Ok(heatmap)
+    }
+    // placements per color in the last window_secs, most used first (ties by color)
+    // the recent feed keeps 2 * RECENT_FEED_BUCKET_SECS of placements, windows inside that
+    // read its buckets only. longer ones (color of the day) scan the whole canvas history
+    // like placement_heatmap, which is kept forever. erases aren't placements and are skipped
+    pub async fn top_colors(
+        &self,
+        canvas_id: &str,
+        window_secs: i64,
+        limit: i32,
+    ) -> Result<Vec<(i32, u64)>, VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        let limit = usize::try_from(limit)?;
+        let since = Utc::now().timestamp() - window_secs.max(0);
+        let mut counts: HashMap<i32, u64> = HashMap::new();
+        if window_secs <= 2 * RECENT_FEED_BUCKET_SECS {
+            for bucket in feed_bucket(since)..=feed_bucket(Utc::now().timestamp()) {
+                let mut rows = self
+                    .session
+                    .execute_iter(self.scan_feed.clone(), (bucket, since))
+                    .await?
+                    .into_typed::<(Option<String>, i32)>();
+                while let Some(row) = rows.next().await {
+                    let (id, color) = row?;
+                    if id.as_deref() == Some(canvas_id) {
+                        *counts.entry(color).or_default() += 1;
+                    }
+                }
+            }
+        } else {
+            for part in &canvas.canvas_part {
+                let mut rows = self
+                    .session
+                    .execute_iter(self.scan_history_colors.clone(), (part,))
+                    .await?
+                    .into_typed::<(i64, PixelData)>();
+                while let Some(row) = rows.next().await {
+                    let (last_placed, pixel) = row?;
+                    if last_placed > since && pixel.color != ERASED_COLOR {
+                        *counts.entry(pixel.color).or_default() += 1;
+                    }
+                }
+            }
+        }
+        let mut top: Vec<(i32, u64)> = counts.into_iter().collect();
+        top.sort_unstable_by_key(|(color, count)| (Reverse(*count), *color));
+        top.truncate(limit);
+        Ok(top)
+    }
+    // painted cells per color, only colors that appear
+    // the whole canvas is tallied from the paged scan, a region (x0, y0, x1, y1) from get_region
+    pub async fn color_histogram(
+        &self,
+        canvas_id: &str,
+        region: Option<(u32, u32, u32, u32)>,
+    ) -> Result<HashMap<i32, u64>, VpError> {
+        let mut histogram: HashMap<i32, u64> = HashMap::new();
+        match region {
+            Some((x0, y0, x1, y1)) => {
+                for (_, _, pixel) in self.get_region(canvas_id, x0, y0, x1, y1).await? {
+                    *histogram.entry(pixel.color).or_default() += 1;
+                }
+            }
+            None => {
+                let mut pixels = Box::pin(self.stream_canvas(canvas_id, Some(STATS_PAGE_SIZE))?);
+                while let Some((_, _, pixel)) = pixels.try_next().await? {
+                    *histogram.entry(pixel.color).or_default() += 1;
+                }
+            }
+        }
+        Ok(histogram)
+    }
+    // biggest 4-connected area painted in color, as (cells, bounding box)
+    // the canvas is scanned once into a dim x dim grid that is flood filled in memory,
+    // so it's as heavy as a png render. (0, 0,0,0,0) when nobody painted the color
+    pub async fn largest_region(
+        &self,
+        canvas_id: &str,
+        color: i32,
+    ) -> Result<(u32, Rect), VpError> {
+        let dim = self.canvas(canvas_id)?.canvas_dim as usize;
+        let mut grid = vec![false; dim * dim];
+        let mut pixels = Box::pin(self.stream_canvas(canvas_id, Some(STATS_PAGE_SIZE))?);
+        while let Some((x, y, pixel)) = pixels.try_next().await? {
+            let (x, y) = (x as usize, y as usize);
+            if x < dim && y < dim && pixel.color == color {
+                grid[x * dim + y] = true;
+            }
+        }
+        largest_component(&mut grid, dim)
+    }
+    // several reads in one round trip, results in op order
+    // ops run concurrently and fail on their own, only an oversized batch fails as a whole
+    pub async fn batch_read(
+        &self,
+        canvas_id: &str,
+        ops: Vec<ReadOp>,
+    ) -> Result<Vec<ReadResult>, VpError> {
+        if ops.len() > MAX_READ_OPS {
+            return Err(VpError::ReadBatchTooLarge);
+        }
+        Ok(join_all(ops.into_iter().map(|op| self.read_op(canvas_id, op))).await)
+    }
+    async fn read_op(&self, canvas_id: &str, op: ReadOp) -> ReadResult {
+        match op {
+            ReadOp::User { address } => self.get_user(&address).await.map(ReadValue::User),
+            ReadOp::Cooldown { address } => self
+                .get_cooldown_remaining(&address)
+                .await
+                .map(ReadValue::RemWait),
+            ReadOp::Pixel { x, y } => self.get_pixel(canvas_id, x, y).await.map(ReadValue::Pixel),
+            ReadOp::Region { x0, y0, x1, y1 } => {
+                self.canvas(canvas_id)?
+                    .check_bounds(x0.max(x1), y0.max(y1))?;
+                self.get_region(canvas_id, x0, y0, x1, y1)
+                    .await
+                    .map(ReadValue::Region)
+            }
+            ReadOp::FillPercentage => self
+                .fill_percentage(canvas_id)
+                .await
+                .map(ReadValue::Percentage),
+        }
+    }
+    // DIAGNOSTIC : painted pixels per canvas_part, to spot unbalanced shards
+    // each COUNT(*) reads its whole partition, partitions are counted one after another
+    // so a check never loads every replica at once. keep it off the hot path : )
+    pub async fn partition_stats(&self, canvas_id: &str) -> Result<Vec<(String, u64)>, VpError> {
+        let mut stats = Vec::new();
+        for part in &self.canvas(canvas_id)?.canvas_part {
+            let rows = self.session.execute(&self.count_part, (part,)).await?;
+            let (count,) = rows
+                .first_row_typed::<(i64,)>()
+                .map_err(VpError::ScyllaTypeErr)?;
+            stats.push((part.clone(), u64::try_from(count)?));
+        }
+        Ok(stats)
+    }
+    // painted share of the canvas, 0.0..=100.0. summed from the partition counts and
+    // cached per canvas, so it lags behind placements by up to FILL_CACHE_SECS : )
+    pub async fn fill_percentage(&self, canvas_id: &str) -> Result<f64, VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        let cached = *canvas.fill.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((at, fill)) = cached {
+            if at.elapsed() < Duration::from_secs(FILL_CACHE_SECS) {
+                return Ok(fill);
+            }
+        }
+        let painted: u64 = self
+            .partition_stats(canvas_id)
+            .await?
+            .iter()
+            .map(|(_, count)| count)
+            .sum();
+        let cells = u64::from(canvas.canvas_dim).pow(2).max(1);
+        let fill = (painted as f64 * 100.0 / cells as f64).clamp(0.0, 100.0);
+        *canvas.fill.lock().unwrap_or_else(|e| e.into_inner()) = Some((Instant::now(), fill));
+        Ok(fill)
+    }
+    // users with most placed pixels
+    // counters are eventually consistent, and scylla can't order by counter
+    // so the whole player_stats table is paged and sorted here : )
+    pub async fn top_players(&self, limit: i32) -> Result<Vec<(String, i64)>, VpError> {
+        let ks = &self.keyspace;
+        let limit = usize::try_from(limit)?;
+        let mut rows = self
+            .session
+            .query_iter(
+                format!("SELECT address, total_placed FROM {ks}.player_stats"),
+                &[],
+            )
+            .await?
+            .into_typed::<(String, Counter)>();
+        let mut players = Vec::new();
+        while let Some(row) = rows.next().await {
+            let (address, placed) = row?;
+            players.push((address, placed.0));
+        }
+        players.sort_unstable_by_key(|(_, placed)| Reverse(*placed));
+        players.truncate(limit);
+        Ok(players)
+    }
+    // placements per configured team, teams that never placed are 0
+    // team_stats only has a row per team, so it's read whole
+    pub async fn team_scores(&self) -> Result<HashMap<String, i64>, VpError> {
+        let ks = &self.keyspace;
+        let mut scores: HashMap<String, i64> =
+            self.teams.iter().map(|team| (team.clone(), 0)).collect();
+        let mut rows = self
+            .session
+            .query_iter(
+                format!("SELECT team, total_placed FROM {ks}.team_stats"),
+                &[],
+            )
+            .await?
+            .into_typed::<(String, Counter)>();
+        while let Some(row) = rows.next().await {
+            let (team, placed) = row?;
+            if let Some(score) = scores.get_mut(&team) {
+                *score = placed.0;
+            }
+        }
+        Ok(scores)
+    }
+    // every placement of the user as csv (x,y,color,timestamp), newest first
+    // a user who never placed gets just the header : )
+    pub async fn export_user_history_csv(&self, address: &str) -> Result<String, VpError> {
+        let address = normalize_address(address)?;
+        let mut rows = self
+            .session
+            .execute_iter(self.scan_player_history.clone(), (&address,))
+            .await?
+            .into_typed::<(i32, i32, i32, i64)>();
+        let mut csv = String::from("x,y,color,timestamp\n");
+        while let Some(row) = rows.next().await {
+            let (x, y, color, last_placed) = row?;
+            csv.push_str(&format!("{},{},{},{}\n", x, y, color, last_placed));
+        }
+        Ok(csv)
+    }
+    // total pixels ever placed on the canvas
+    // the counter is split over GLOBAL_STAT_SHARDS rows, each placement bumps a random one.
+    // a single row would take every placement of the canvas on one replica set,
+    // sharding spreads those writes at the cost of reading and summing all shards here.
+    // counters are not idempotent, a timed out increment may be lost or applied twice : )
+    pub async fn total_placements(&self) -> Result<i64, VpError> {
+        let shards: Vec<i32> = (0..GLOBAL_STAT_SHARDS).collect();
+        let rows = self.session.execute(&self.get_global, (shards,)).await?;
+        rows.rows_typed_or_empty::<(Counter,)>()
+            .map(|row| Ok(row?.0 .0))
+            .sum()
+    }
+    // last `limit` placements of a user, newest first
+    // empty for users who never placed : )
+    pub async fn get_user_recent(
+        &self,
+        address: &str,
+        limit: i32,
+    ) -> Result<Vec<UpdatePixel>, VpError> {
+        let address = &normalize_address(address)?;
+        let rows = self
+            .session
+            .execute(&self.get_player_history, (address, limit))
+            .await?;
+        rows.rows_typed_or_empty::<(i32, i32, i32)>()
+            .map(|row| {
+                let (x, y, color) = row?;
+                Ok(UpdatePixel {
+                    address: Some(address.to_string()),
+                    loc: PixelLoc {
+                        x: u32::try_from(x)?,
+                        y: u32::try_from(y)?,
+                    },
+                    color: u32::try_from(color)?,
+                    signature: None,
+                    nonce: None,
+                    idempotency_key: None,
+                    placed_at: None,
+                    team: None,
+                    tag: None,
+                })
+            })
+            .collect()
+    }
+    // newest `limit` placements of every canvas, from the current and previous bucket
+    // so the ticker doesn't go empty right after the hour : )
+    pub async fn recent_placements(&self, limit: i32) -> Result<Vec<UpdatePixel>, VpError> {
+        let bucket = feed_bucket(Utc::now().timestamp());
+        let (current, previous) = tokio::try_join!(
+            self.session.execute(&self.get_recent, (bucket, limit)),
+            self.session.execute(&self.get_recent, (bucket - 1, limit)),
+        )?;
+        current
+            .rows_typed_or_empty::<(String, i32, i32, i32)>()
+            .chain(previous.rows_typed_or_empty::<(String, i32, i32, i32)>())
+            .take(usize::try_from(limit)?)
+            .map(|row| {
+                let (address, x, y, color) = row?;
+                Ok(UpdatePixel {
+                    address: Some(address),
+                    loc: PixelLoc {
+                        x: u32::try_from(x)?,
+                        y: u32::try_from(y)?,
+                    },
+                    color: u32::try_from(color)?,
+                    signature: None,
+                    nonce: None,
+                    idempotency_key: None,
+                    placed_at: None,
+                    team: None,
+                    tag: None,
+                })
+            })
+            .collect()
+    }
+    // placements of a canvas after since_ts, oldest first, to catch up before subscribing
+    // only the feed ttl is retained and at most MAX_DELTA_LEN placements are returned,
+    // older or bigger gaps are DeltaTooOld and need a full resync
+    pub async fn delta_since(
+        &self,
+        canvas_id: &str,
+        since_ts: i64,
+    ) -> Result<Vec<PixelUpdate>, VpError> {
+        self.canvas(canvas_id)?;
+        let now = Utc::now().timestamp();
+        if since_ts < now - 2 * RECENT_FEED_BUCKET_SECS {
+            return Err(VpError::DeltaTooOld);
+        }
+        let rows = futures::stream::iter((feed_bucket(since_ts)..=feed_bucket(now)).rev())
+            .then(|bucket| {
+                self.session
+                    .execute_iter(self.get_delta.clone(), (bucket, since_ts))
+            })
+            .map_err(VpError::from)
+            .map_ok(|rows| rows.into_typed::<FeedRow>().map_err(VpError::from))
+            .try_flatten();
+        collect_delta(canvas_id, rows).await
+    }
+    // last `limit` placements of a pixel, newest first
+    pub async fn get_pixel_history(
+        &self,
+        canvas_id: &str,
+        x: u32,
+        y: u32,
+        limit: i32,
+    ) -> Result<Vec<PixelData>, VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        canvas.check_bounds(x, y)?;
+        let part = canvas.partition_name(x, y);
+        let (ix, iy) = (i32::try_from(x)?, i32::try_from(y)?);
+        let rows = self
+            .session
+            .execute(&self.get_history, (part, ix, iy, limit))
+            .await?;
+        rows.rows_typed_or_empty::<(PixelData,)>()
+            .map(|row| Ok(row?.0))
+            .collect()
+    }
+    // one page of a pixel's history, newest first, older than before_ts when set
+    // the cursor is the oldest last_placed of the page, pass it as before_ts for the next one.
+    // None when exhausted. history rows are keyed by the second, so 2 placements on a pixel
+    // within one second keep only the last row, the cursor itself never splits a second : )
+    pub async fn get_pixel_history_page(
+        &self,
+        canvas_id: &str,
+        x: u32,
+        y: u32,
+        before_ts: Option<i64>,
+        limit: i32,
+    ) -> Result<(Vec<PixelData>, Option<i64>), VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        canvas.check_bounds(x, y)?;
+        let part = canvas.partition_name(x, y);
+        let (ix, iy) = (i32::try_from(x)?, i32::try_from(y)?);
+        let rows = match before_ts {
+            Some(ts) => {
+                self.session
+                    .execute(&self.get_history_before, (part, ix, iy, ts, limit))
+                    .await?
+            }
+            None => {
+                self.session
+                    .execute(&self.get_history, (part, ix, iy, limit))
+                    .await?
+            }
+        };
+        let page = rows
+            .rows_typed_or_empty::<(PixelData,)>()
+            .map(|row| Ok(row?.0))
+            .collect::<Result<Vec<_>, VpError>>()?;
+        // a short page is the last one
+        let cursor = match page.last() {
+            Some(oldest) if page.len() == usize::try_from(limit)? => Some(oldest.last_placed),
+            _ => None,
+        };
+        Ok((page, cursor))
+    }
+    // placement of a pixel that was active at ts (seconds), for disputes / minting
+    // NoPixelData when the pixel was unpainted at ts. reverted placements are gone from history : )
+    pub async fn owner_at(
+        &self,
+        canvas_id: &str,
+        x: u32,
+        y: u32,
+        ts: i64,
+    ) -> Result<PixelData, VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        canvas.check_bounds(x, y)?;
+        let part = canvas.partition_name(x, y);
+        let (ix, iy) = (i32::try_from(x)?, i32::try_from(y)?);
+        let rows = self
+            .session
+            .execute(&self.get_owner_at, (part, ix, iy, ts))
+            .await?;
+        match rows.first_row_typed::<(PixelData,)>() {
+            Ok((pixel,)) if pixel.color == ERASED_COLOR => Err(VpError::NoPixelData),
+            Ok((pixel,)) => Ok(pixel),
+            Err(FirstRowTypedError::RowsEmpty) => Err(VpError::NoPixelData),
+            Err(e) => Err(VpError::ScyllaTypeErr(e)),
+        }
+    }
+    // pixels in input order, None for the ones with no pixel data
+    pub async fn get_pixels(
+        &self,
+        canvas_id: &str,
+        coords: &[(u32, u32)],
+    ) -> Result<Vec<Option<PixelData>>, VpError> {
+        let ks = &self.keyspace;
+        let canvas = self.canvas(canvas_id)?;
+        for (x, y) in coords {
+            canvas.check_bounds(*x, *y)?;
+        }
+        let mut parts: HashMap<usize, Vec<(i32, i32)>> = HashMap::new();
+        for (x, y) in coords {
+            let pindex = canvas.partition_index(*x, *y);
+            parts
+                .entry(pindex)
+                .or_default()
+                .push((i32::try_from(*x)?, i32::try_from(*y)?));
+        }
+        // tuple count varies per call so the IN query is built per partition
+        // coordinates are ints, only canvas_part is bound : )
+        let found = try_join_all(parts.into_iter().map(|(pindex, cells)| async move {
+            let cells = cells
+                .iter()
+                .map(|(x, y)| format!("({},{})", x, y))
+                .collect::<Vec<_>>()
+                .join(",");
+            let rows = self
+                .session
+                .query(
+                    format!(
+                        "SELECT x, y, data FROM {ks}.canvas WHERE canvas_part = ? AND (x,y) IN ({})",
+                        cells
+                    ),
+                    (&canvas.canvas_part[pindex],),
+                )
+                .await?;
+            rows.rows_typed_or_empty::<(i32, i32, PixelData)>()
+                .map(|row| {
+                    let (x, y, pixel) = row?;
+                    Ok(((u32::try_from(x)?, u32::try_from(y)?), pixel))
+                })
+                .collect::<Result<Vec<_>, VpError>>()
+        }))
+        .await?;
+        let found: HashMap<(u32, u32), PixelData> = found.into_iter().flatten().collect();
+        Ok(coords.iter().map(|c| found.get(c).cloned()).collect())
+    }
+    // a pixel and its 4 neighbors for flood fill tools, one IN query per partition touched
+    // None is off the canvas, unpainted cells come back as the background color with no owner
+    pub async fn get_pixel_neighborhood(
+        &self,
+        canvas_id: &str,
+        x: u32,
+        y: u32,
+    ) -> Result<Neighborhood, VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        canvas.check_bounds(x, y)?;
+        let dim = canvas.canvas_dim;
+        let cells = [
+            Some((x, y)),
+            y.checked_sub(1).map(|y| (x, y)),
+            (y + 1 < dim).then_some((x, y + 1)),
+            x.checked_sub(1).map(|x| (x, y)),
+            (x + 1 < dim).then_some((x + 1, y)),
+        ];
+        let coords: Vec<(u32, u32)> = cells.iter().flatten().copied().collect();
+        let mut found = self.get_pixels(canvas_id, &coords).await?.into_iter();
+        let empty = self.empty_color(self.canvas(canvas_id)?);
+        let mut pixels = cells.map(|cell| {
+            cell.map(|_| {
+                found.next().flatten().unwrap_or_else(|| PixelData {
+                    address: String::new(),
+                    color: empty,
+                    last_placed: 0,
+                    tag: None,
+                })
+            })
+        });
+        let mut take = |i: usize| pixels[i].take();
+        Ok(Neighborhood {
+            center: take(0).ok_or(VpError::OutOfBounds { x, y })?,
+            up: take(1),
+            down: take(2),
+            left: take(3),
+            right: take(4),
+        })
+    }
+    // (x0,y0) and (x1,y1) are inclusive corners of the region
+    // pixels of the region placed after since_ts, for catching up after a reconnect
+    // last_placed lives in the pixel_data UDT, not in the key, so scylla can't filter on it.
+    // this is get_region + a client side filter : same cost as a full region fetch
+    // (one query per column, MAX_REGION_AREA cap) and only the transfer to the client shrinks.
+    pub async fn region_since(
+        &self,
+        canvas_id: &str,
+        x0: u32,
+        y0: u32,
+        x1: u32,
+        y1: u32,
+        since_ts: i64,
+    ) -> Result<Vec<(u32, u32, PixelData)>, VpError> {
+        self.canvas(canvas_id)?.check_region(x0, y0, x1, y1)?;
+        let mut pixels = self.get_region(canvas_id, x0, y0, x1, y1).await?;
+        pixels.retain(|(_, _, pixel)| pixel.last_placed > since_ts);
+        Ok(pixels)
+    }
+    // region as bytes, see "Packed region format" in the README
+    // format flag, x0 y0 width height (u32 BE), then one color byte per cell, x major like the bitfield
+    // truecolor canvases use 3 bytes (rgb) per cell
+    pub async fn get_region_packed(
+        &self,
+        canvas_id: &str,
+        x0: u32,
+        y0: u32,
+        x1: u32,
+        y1: u32,
+    ) -> Result<Vec<u8>, VpError> {
+        let (header, cells) = self.pack_region(canvas_id, x0, y0, x1, y1).await?;
+        Ok([&[PACKED_RAW][..], &header, &cells].concat())
+    }
+    // packed region, with the cells run length encoded when that is smaller
+    // runs are (length, color) byte pairs, so mostly empty regions shrink to a few hundred bytes : )
+    // truecolor regions are always raw
+    pub async fn get_region_rle(
+        &self,
+        canvas_id: &str,
+        x0: u32,
+        y0: u32,
+        x1: u32,
+        y1: u32,
+    ) -> Result<Vec<u8>, VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        canvas.check_region(x0, y0, x1, y1)?;
+        let (header, cells) = self.pack_region(canvas_id, x0, y0, x1, y1).await?;
+        if canvas.color_mode.cell_bytes() > 1 {
+            return Ok([&[PACKED_RAW][..], &header, &cells].concat());
+        }
+        let mut runs: Vec<u8> = Vec::new();
+        for color in cells.iter().copied() {
+            match runs.len().checked_sub(2) {
+                Some(last) if runs[last + 1] == color && runs[last] < u8::MAX => runs[last] += 1,
+                _ => runs.extend_from_slice(&[1, color]),
+            }
+        }
+        if runs.len() < cells.len() {
+            Ok([&[PACKED_RLE][..], &header, &runs].concat())
+        } else {
+            Ok([&[PACKED_RAW][..], &header, &cells].concat())
+        }
+    }
+    // (header, cell_bytes per cell) of a region
+    async fn pack_region(
+        &self,
+        canvas_id: &str,
+        x0: u32,
+        y0: u32,
+        x1: u32,
+        y1: u32,
+    ) -> Result<(Vec<u8>, Vec<u8>), VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        canvas.check_region(x0, y0, x1, y1)?;
+        let pixels = self.get_region(canvas_id, x0, y0, x1, y1).await?;
+        let (width, height) = (x1 - x0 + 1, y1 - y0 + 1);
+        let header = [x0, y0, width, height]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+        let mode = &canvas.color_mode;
+        let n = mode.cell_bytes();
+        let mut empty = vec![0; n];
+        mode.encode(self.empty_color(canvas), &mut empty);
+        let mut cells = empty.repeat(usize::try_from(u64::from(width) * u64::from(height))?);
+        for (x, y, pixel) in pixels {
+            let offset = usize::try_from((x - x0) * height + (y - y0))? * n;
+            mode.encode(pixel.color, &mut cells[offset..offset + n]);
+        }
+        Ok((header, cells))
+    }
+    // every cell of the region, unpainted ones as the background color
+    // with no address and a last_placed of 0
+    pub async fn get_region_filled(
+        &self,
+        canvas_id: &str,
+        x0: u32,
+        y0: u32,
+        x1: u32,
+        y1: u32,
+    ) -> Result<Vec<(u32, u32, PixelData)>, VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        canvas.check_region(x0, y0, x1, y1)?;
+        let painted = self.get_region(canvas_id, x0, y0, x1, y1).await?;
+        let empty = self.empty_color(canvas);
+        // both are x major, so painted cells are merged in a single pass
+        let mut painted = painted.into_iter().peekable();
+        let mut res = Vec::new();
+        for x in x0..=x1 {
+            for y in y0..=y1 {
+                match painted.next_if(|(px, py, _)| (*px, *py) == (x, y)) {
+                    Some(cell) => res.push(cell),
+                    None => res.push((
+                        x,
+                        y,
+                        PixelData {
+                            address: String::new(),
+                            color: empty,
+                            last_placed: 0,
+                            tag: None,
+                        },
+                    )),
+                }
+            }
+        }
+        Ok(res)
+    }
+    pub async fn get_region(
+        &self,
+        canvas_id: &str,
+        x0: u32,
+        y0: u32,
+        x1: u32,
+        y1: u32,
+    ) -> Result<Vec<(u32, u32, PixelData)>, VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        canvas.check_region(x0, y0, x1, y1)?;
+        let area = (u64::from(x1 - x0) + 1) * (u64::from(y1 - y0) + 1);
+        if area > MAX_REGION_AREA {
+            return Err(VpError::RegionTooLarge);
+        }
+        // one query per column of each sub-rectangle, y range is a clustering slice
+        let mut cols = Vec::new();
+        for (pindex, xr, yr) in canvas.split_region(x0..=x1, y0..=y1) {
+            let (iy0, iy1) = (i32::try_from(*yr.start())?, i32::try_from(*yr.end())?);
+            for x in xr {
+                cols.push((&canvas.canvas_part[pindex], x, i32::try_from(x)?, iy0, iy1));
+            }
+        }
+        let mut res: Vec<(u32, u32, PixelData)> = futures::stream::iter(cols)
+            .map(|(part, x, ix, iy0, iy1)| async move {
+                let rows = self
+                    .session
+                    .execute(&self.get_pixel_col, (part, ix, iy0, iy1))
+                    .await?;
+                rows.rows_typed_or_empty::<(i32, PixelData)>()
+                    .map(|row| {
+                        let (iy, pixel) = row?;
+                        Ok((x, u32::try_from(iy)?, pixel))
+                    })
+                    .collect::<Result<Vec<_>, VpError>>()
+            })
+            .buffer_unordered(REGION_CONCURRENCY)
+            .try_concat()
+            .await?;
+        res.sort_unstable_by_key(|(x, y, _)| (*x, *y));
+        Ok(res)
+    }
+    // keccak256 of the region's painted (x, y, color) triples, first 8 bytes big endian
+    // get_region sorts by (x, y), so equal pixels hash equal on any node or shard layout
+    // owners and timestamps are left out, only what the canvas shows is compared : )
+    pub async fn region_checksum(
+        &self,
+        canvas_id: &str,
+        x0: u32,
+        y0: u32,
+        x1: u32,
+        y1: u32,
+    ) -> Result<u64, VpError> {
+        let mut hasher = Keccak256::new();
+        for (x, y, pixel) in self.get_region(canvas_id, x0, y0, x1, y1).await? {
+            hasher.update(x.to_be_bytes());
+            hasher.update(y.to_be_bytes());
+            hasher.update(pixel.color.to_be_bytes());
+        }
+        let digest = hasher.finalize();
+        let mut head = [0u8; 8];
+        head.copy_from_slice(&digest[..8]);
+        Ok(u64::from_be_bytes(head))
+    }
+    // painted cells of the (inclusive) region, one COUNT(*) per column slice
+    // scylla still reads the rows, only the count crosses the wire : )
+    pub async fn count_painted(
+        &self,
+        canvas_id: &str,
+        x0: u32,
+        y0: u32,
+        x1: u32,
+        y1: u32,
+    ) -> Result<u64, VpError> {
+        let canvas = self.canvas(canvas_id)?;
+        canvas.check_region(x0, y0, x1, y1)?;
+        let area = (u64::from(x1 - x0) + 1) * (u64::from(y1 - y0) + 1);
+        if area > MAX_REGION_AREA {
+            return Err(VpError::RegionTooLarge);
+        }
+        let mut cols = Vec::new();
+        for (pindex, xr, yr) in canvas.split_region(x0..=x1, y0..=y1) {
+            let (iy0, iy1) = (i32::try_from(*yr.start())?, i32::try_from(*yr.end())?);
+            for x in xr {
+                cols.push((&canvas.canvas_part[pindex], i32::try_from(x)?, iy0, iy1));
+            }
+        }
+        futures::stream::iter(cols)
+            .map(|(part, ix, iy0, iy1)| async move {
+                let rows = self
+                    .session
+                    .execute(&self.count_pixel_col, (part, ix, iy0, iy1))
+                    .await?;
+                let (count,) = rows
+                    .first_row_typed::<(i64,)>()
+                    .map_err(VpError::ScyllaTypeErr)?;
+                Ok(u64::try_from(count)?)
+            })
+            .buffer_unordered(REGION_CONCURRENCY)
+            .try_fold(0, |total, count| async move { Ok(total + count) })
+            .await
+    }
+    // ADMIN ONLY : wipes every pixel in the (inclusive) region
+    // returns the number of painted pixels that were removed
+    pub async fn clear_region(
+        &self,
+        canvas_id: &str,
+        x0: u32,
+        y0: u32,
+        x1: u32,
+        y1: u32,
+    ) -> Result<u64, VpError> {
+        let _write = self.writes.enter()?;
+        let canvas = self.canvas(canvas_id)?;
+        canvas.check_region(x0, y0, x1, y1)?;
+        // also does the RegionTooLarge check : )
+        let painted = self.get_region(canvas_id, x0, y0, x1, y1).await?;
+        let mut cols = Vec::new();
+        for (pindex, xr, yr) in canvas.split_region(x0..=x1, y0..=y1) {
+            let (iy0, iy1) = (i32::try_from(*yr.start())?, i32::try_from(*yr.end())?);
+            for x in xr {
+                cols.push((&canvas.canvas_part[pindex], i32::try_from(x)?, iy0, iy1));
+            }
+        }
+        futures::stream::iter(cols)
+            .map(|(part, ix, iy0, iy1)| {
+                self.session
+                    .execute(&self.delete_pixel_col, (part, ix, iy0, iy1))
+                    .map_err(VpError::from)
+            })
+            .buffer_unordered(REGION_CONCURRENCY)
+            .try_collect::<Vec<_>>()
+            .await?;
+        if let Some(cache) = &self.pixel_cache {
+            let cleared = painted
+                .iter()
+                .map(|(x, y, _)| cache.del(canvas.partition_name(*x, *y), *x, *y));
+            futures::future::join_all(cleared).await;
+        }
+        self.touch(canvas_id, true).await;
+        Ok(painted.len() as u64)
+    }
+    // ADMIN ONLY : wipes every pixel still owned by address, for banned wallets
+    // cells painted over by someone else since are left alone
+    // returns the number of pixels cleared
+    pub async fn remove_user_pixels(&self, address: &str) -> Result<u64, VpError> {
+        let _write = self.writes.enter()?;
+        let address = &normalize_address(address)?;
+        let mut rows = self
+            .session
+            .execute_iter(self.get_user_pixels.clone(), (address,))
+            .await?
+            .into_typed::<(String, i32, i32)>();
+        let mut painted = Vec::new();
+        while let Some(row) = rows.next().await {
+            painted.push(row?);
+        }
+        let cleared = futures::stream::iter(painted)
+            .map(|(part, ix, iy)| async move {
+                let rows = self
+                    .session
+                    .execute(&self.get_pixel, (&part, ix, iy))
+                    .await?;
+                match rows.first_row_typed::<(PixelData,)>() {
+                    Ok((pixel,)) if pixel.address == *address => {}
+                    Ok(_) | Err(FirstRowTypedError::RowsEmpty) => return Ok(0),
+                    Err(e) => return Err(VpError::ScyllaTypeErr(e)),
+                }
+                self.session
+                    .execute(&self.delete_pixel_col, (&part, ix, iy, iy))
+                    .await?;
+                if let Some(cache) = &self.pixel_cache {
+                    cache
+                        .del(&part, u32::try_from(ix)?, u32::try_from(iy)?)
+                        .await;
+                }
+                Ok(1)
+            })
+            .buffer_unordered(REGION_CONCURRENCY)
+            .try_fold(0, |total, cleared| async move { Ok(total + cleared) })
+            .await?;
+        self.session
+            .execute(&self.delete_user_pixels, (address,))
+            .await?;
+        if cleared > 0 {
+            // the index spans canvases, just bump them all
+            for canvas_id in self.canvases.keys() {
+                self.touch(canvas_id, true).await;
+            }
+        }
+        Ok(cleared)
+    }
+    // pixels of the region address still owns, for highlighting them in the viewport
+    // walks the user_pixels index and keeps the cells nobody painted over since : )
+    pub async fn user_pixels_in_region(
+        &self,
+        canvas_id: &str,
+        address: &str,
+        x0: u32,
+        y0: u32,
+        x1: u32,
+        y1: u32,
+    ) -> Result<Vec<(u32, u32, PixelData)>, VpError> {
+        let address = &normalize_address(address)?;
+        let canvas = self.canvas(canvas_id)?;
+        canvas.check_region(x0, y0, x1, y1)?;
+        let region = Rect::new(x0, y0, x1, y1);
+        if region.area() > MAX_REGION_AREA {
+            return Err(VpError::RegionTooLarge);
+        }
+        let mut rows = self
+            .session
+            .execute_iter(self.get_user_pixels.clone(), (address,))
+            .await?
+            .into_typed::<(String, i32, i32)>();
+        let mut coords = Vec::new();
+        while let Some(row) = rows.next().await {
+            let (part, ix, iy) = row?;
+            let (x, y) = (u32::try_from(ix)?, u32::try_from(iy)?);
+            if region.contains(x, y) && canvas.canvas_part[canvas.partition_index(x, y)] == part {
+                coords.push((x, y));
+            }
+        }
+        let pixels = try_join_all(
+            coords
+                .chunks(MAX_USERS_BATCH)
+                .map(|chunk| self.get_pixels(canvas_id, chunk)),
+        )
+        .await?;
+        Ok(coords
+            .into_iter()
+            .zip(pixels.into_iter().flatten())
+            .filter_map(|((x, y), pixel)| Some((x, y, pixel?)))
+            .filter(|(_, _, pixel)| pixel.address == *address)
+            .collect())
+    }
+    // ADMIN ONLY : undo the latest placement of a pixel
+    // drops the newest history entry and restores the one before it,
+    // without a prior entry the pixel is deleted. player rows (cooldown) are untouched : )
+    pub async fn revert_pixel(
+        &self,
+        canvas_id: &str,
+        x: u32,
+        y: u32,
+    ) -> Result<Option<PixelData>, VpError> {
+        let _write = self.writes.enter()?;
+        let canvas = self.canvas(canvas_id)?;
+        canvas.check_bounds(x, y)?;
+        let (ix, iy) = (i32::try_from(x)?, i32::try_from(y)?);
+        let part = canvas.partition_name(x, y);
+        let mut history = self
+            .get_pixel_history(canvas_id, x, y, 2)
+            .await?
+            .into_iter();
+        if let Some(latest) = history.next() {
+            self.session
+                .execute(&self.delete_history, (part, ix, iy, latest.last_placed))
+                .await?;
+        }
+        // restoring an erase is restoring the empty pixel
+        let previous = history.next().filter(|pixel| pixel.color != ERASED_COLOR);
+        match &previous {
+            Some(pixel) => {
+                self.session
+                    .execute(&self.insert_pixel, (part, ix, iy, pixel))
+                    .await?;
+            }
+            None => {
+                self.session
+                    .execute(&self.delete_pixel_col, (part, ix, iy, iy))
+                    .await?;
+            }
+        }
+        if let Some(cache) = &self.pixel_cache {
+            match &previous {
+                Some(pixel) => cache.set(part, x, y, pixel).await,
+                None => cache.del(part, x, y).await,
+            }
+        }
+        self.touch(canvas_id, true).await;
+        Ok(previous)
+    }
+}
+
+// row of a placement already written by an LWT, with the last_placed it used
+#[derive(Clone, Copy)]
+enum Written {
+    Nothing,
+    Canvas(i64),
+    Player(i64),
+}
+
+// one check of explain_placement, error is the VpError message it failed with
+#[derive(Serialize)]
+pub struct PlacementGate {
+    pub gate: &'static str,
+    pub passed: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PlacementDecision {
+    // every gate passed
+    pub allowed: bool,
+    pub gates: Vec<PlacementGate>,
+}
+impl Default for PlacementDecision {
+    fn default() -> Self {
+        Self {
+            allowed: true,
+            gates: Vec::new(),
+        }
+    }
+}
+impl PlacementDecision {
+    pub fn push(&mut self, gate: &'static str, res: Result<(), VpError>) {
+        self.allowed &= res.is_ok();
+        self.gates.push(PlacementGate {
+            gate,
+            passed: res.is_ok(),
+            error: res.err().map(|e| e.to_string()),
+        });
+    }
+}
+
+// what the next placement of an address is checked against / stored with
+#[derive(Clone, Copy)]
+pub struct NextPlacement {
+    pub streak: i32,
+    // nonce the placement must be signed with
+    pub nonce: u64,
+    // cooldown the placement passed, 0 when it skipped it (admin, grace, ink budget)
+    pub cooldown_secs: i64,
+    // (ink, as of) before the placement, None without an ink budget or for admins
+    pub ink: Option<(i64, i64)>,
+    // (utc day, placements of the day before this one), None without a daily quota or for admins
+    pub daily_placed: Option<(i64, i64)>,
+}
+
+// pixel (x,y) and its neighbors, up is (x, y-1) : rows grow downwards like the png
+#[derive(Serialize)]
+pub struct Neighborhood {
+    pub center: PixelData,
+    pub up: Option<PixelData>,
+    pub down: Option<PixelData>,
+    pub left: Option<PixelData>,
+    pub right: Option<PixelData>,
+}
+
+// png export options, the default is the plain canvas_dim x canvas_dim image
+pub struct RenderOptions {
+    // every cell becomes scale x scale pixels
+    pub scale: u32,
+    pub watermark: Option<String>,
+}
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            scale: 1,
+            watermark: None,
+        }
+    }
+}
+
+// one read of a batch_read, eg: {"op":"pixel","x":3,"y":4}
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ReadOp {
+    User { address: String },
+    Cooldown { address: String },
+    Pixel { x: u32, y: u32 },
+    // inclusive corners
+    Region { x0: u32, y0: u32, x1: u32, y1: u32 },
+    FillPercentage,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum ReadValue {
+    User(UserDetails),
+    // seconds left of the cooldown
+    RemWait(i64),
+    Pixel(PixelData),
+    Region(Vec<(u32, u32, PixelData)>),
+    Percentage(f64),
+}
+
+pub type ReadResult = Result<ReadValue, VpError>;
+
+// served to frontends so they self-configure
+#[derive(Serialize)]
+pub struct CanvasInfo {
+    pub dim: u32,
+    pub palette: Vec<(i32, String)>,
+    // colors are 0xRRGGBB, palette is empty
+    pub truecolor: bool,
+    // base cooldown, before any streak scaling
+    pub cooldown_secs: i64,
+    // replaces the cooldown when set
+    pub ink: Option<InkBudget>,
+    // max placements of a wallet per utc day, None is unlimited
+    pub daily_quota: Option<i64>,
+    // palette index of unpainted cells
+    pub background_color: i32,
+    // admin only rectangles
+    pub reserved_regions: Vec<Rect>,
+    // (region, cooldown secs) overriding cooldown_secs
+    pub region_cooldowns: Vec<(Rect, i64)>,
+    pub partitions: Vec<String>,
+}
+
+// a canvas_part and the cells it covers, end exclusive. empty ranges past the canvas edge
+// happen when canvas_dim doesn't split evenly
+#[derive(Serialize, Clone, Debug)]
+pub struct PartitionInfo {
+    pub name: String,
+    pub x_range: Range<u32>,
+    pub y_range: Range<u32>,
+}
+
+// broadcast to subscribers after every placement
+#[derive(Clone, Debug, Serialize)]
+pub struct PixelUpdate {
+    pub canvas_id: String,
+    pub x: u32,
+    pub y: u32,
+    pub color: u32,
+    pub address: String,
+}
+
+// pixels of one partition, paged as the stream is polled
+fn scan_partition(
+    session: Arc<Session>,
+    scan: PreparedStatement,
+    part: String,
+) -> impl Stream<Item = Result<(u32, u32, PixelData), VpError>> {
+    futures::stream::once(async move { session.execute_iter(scan, (part,)).await })
+        .map_err(VpError::from)
+        .map_ok(|rows| {
+            rows.into_typed::<(i32, i32, PixelData)>()
+                .map_err(VpError::from)
+        })
+        .try_flatten()
+        .and_then(|(x, y, pixel)| async move { Ok((u32::try_from(x)?, u32::try_from(y)?, pixel)) })
+}
+
+// (canvas_id, address, x, y, color) of a recent_feed row, canvas_id None before deltas
+type FeedRow = (Option<String>, String, i32, i32, i32);
+
+// delta of canvas_id from feed rows newest first, every bucket read to the end
+// only rows of canvas_id count towards MAX_DELTA_LEN
+async fn collect_delta(
+    canvas_id: &str,
+    rows: impl Stream<Item = Result<FeedRow, VpError>>,
+) -> Result<Vec<PixelUpdate>, VpError> {
+    let mut rows = std::pin::pin!(rows);
+    let mut delta = Vec::new();
+    while let Some(row) = rows.next().await {
+        let (id, address, x, y, color) = row?;
+        if id.as_deref() != Some(canvas_id) {
+            continue;
+        }
+        if delta.len() == MAX_DELTA_LEN {
+            return Err(VpError::DeltaTooOld);
+        }
+        delta.push(PixelUpdate {
+            canvas_id: canvas_id.to_string(),
+            x: u32::try_from(x)?,
+            y: u32::try_from(y)?,
+            color: u32::try_from(color)?,
+            address,
+        });
+    }
+    delta.reverse();
+    Ok(delta)
+}
+
+// [applied] column of an LWT result
+fn lwt_applied(rows: QueryResult) -> bool {
+    rows.first_row()
+        .ok()
+        .and_then(|row| row.columns.into_iter().next().flatten())
+        .and_then(|applied| applied.as_boolean())
+        .unwrap_or(false)
+}
+
+// biggest 4-connected set cell of an x major dim x dim grid, the grid is cleared on the way
+fn largest_component(grid: &mut [bool], dim: usize) -> Result<(u32, Rect), VpError> {
+    let mut best = (0, Rect::new(0, 0, 0, 0));
+    let mut stack = Vec::new();
+    for start in 0..grid.len() {
+        if !grid[start] {
+            continue;
+        }
+        grid[start] = false;
+        stack.push(start);
+        let (mut size, mut x0, mut y0, mut x1, mut y1) = (0u32, dim, dim, 0, 0);
+        while let Some(cell) = stack.pop() {
+            let (x, y) = (cell / dim, cell % dim);
+            size += 1;
+            (x0, y0, x1, y1) = (x0.min(x), y0.min(y), x1.max(x), y1.max(y));
+            let neighbors = [
+                (x > 0).then(|| cell - dim),
+                (x + 1 < dim).then(|| cell + dim),
+                (y > 0).then(|| cell - 1),
+                (y + 1 < dim).then(|| cell + 1),
+            ];
+            for next in neighbors.into_iter().flatten() {
+                if grid[next] {
+                    grid[next] = false;
+                    stack.push(next);
+                }
+            }
+        }
+        if size > best.0 {
+            let [x0, y0, x1, y1] = [x0, y0, x1, y1].map(u32::try_from);
+            best = (size, Rect::new(x0?, y0?, x1?, y1?));
+        }
+    }
+    Ok(best)
+}
+
+// current value of a column in a rejected LWT result, None when the row doesn't exist
+fn lwt_column(rows: &QueryResult, name: &str) -> Option<CqlValue> {
+    let index = rows.col_specs.iter().position(|spec| spec.name == name)?;
+    rows.rows
+        .as_ref()?
+        .first()?
+        .columns
+        .get(index)
+        .cloned()
+        .flatten()
+}
+
+// normalized address of a placement
+fn req_address(req: &UpdatePixel) -> Result<String, VpError> {
+    normalize_address(req.address.as_ref().ok_or(VpError::InvalidUser)?)
+}
+
+// a migration failing because its column / field exists, anything else Invalid is a real error
+// scylla : "conflicts with an existing column", cassandra and udt fields : "already exists"
+fn already_applied(err: &QueryError) -> bool {
+    match err {
+        QueryError::DbError(DbError::Invalid, msg) => {
+            msg.contains("already exists") || msg.contains("conflicts with an existing column")
+        }
+        _ => false,
+    }
+}
+
+// MIGRATIONS still to run on a schema at version, each with the version it leaves behind
+// nothing when version is this build's or newer
+fn pending_migrations(version: usize) -> impl Iterator<Item = (usize, &'static str)> {
+    MIGRATIONS
+        .iter()
+        .enumerate()
+        .skip(version)
+        .map(|(i, migration)| (i + 1, *migration))
+}
+
+// letter then letters, digits or _, 48 chars at most like scylla wants
+fn is_keyspace_name(name: &str) -> bool {
+    name.len() <= 48
+        && name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// count more placements on top of placed stay within quota, the last one may hit it exactly
+fn fits_quota(placed: i64, count: i64, quota: i64) -> bool {
+    placed + count <= quota
+}
+
+// page size of a range read, the call's own or the configured one. a page holds 1 row at least
+fn effective_page_size(page_size: Option<i32>, configured: i32) -> i32 {
+    page_size.unwrap_or(configured).max(1)
+}
+
+// settings the manager puts on its statements, prepared ones can't be built without a cluster
+trait StatementTuning {
+    fn tune_page_size(&mut self, page_size: i32);
+    fn tune_consistency(&mut self, level: Consistency);
+}
+impl StatementTuning for PreparedStatement {
+    fn tune_page_size(&mut self, page_size: i32) {
+        self.set_page_size(page_size);
+    }
+    fn tune_consistency(&mut self, level: Consistency) {
+        self.set_consistency(level);
+    }
+}
+impl StatementTuning for Query {
+    fn tune_page_size(&mut self, page_size: i32) {
+        self.set_page_size(page_size);
+    }
+    fn tune_consistency(&mut self, level: Consistency) {
+        self.set_consistency(level);
+    }
+}
+
+// configured read / write level of a statement, None keeps the session's default
+fn set_level(statement: &mut impl StatementTuning, level: Option<DbConsistency>) {
+    if let Some(level) = level {
+        statement.tune_consistency(level.into());
+    }
+}
+
+// pages of a range read statement, see effective_page_size
+fn set_paging(statement: &mut impl StatementTuning, page_size: Option<i32>, configured: i32) {
+    statement.tune_page_size(effective_page_size(page_size, configured));
+}
+
+// color_quota / daily_quota row of today (utc)
+fn quota_day() -> i64 {
+    quota_day_at(Utc::now().timestamp())
+}
+
+// utc day of the unix time ts, rows of a new day start from 0
+fn quota_day_at(ts: i64) -> i64 {
+    ts.div_euclid(86400)
+}
+
+// seconds from now until day (a quota_day) is over, at least 1 as a zero ttl never expires
+fn secs_to_day_end(day: i64, now: i64) -> i64 {
+    ((day + 1) * 86400 - now).max(1)
+}
+
+// recent_feed partition of a placement time
+fn feed_bucket(ts: i64) -> i64 {
+    ts.div_euclid(RECENT_FEED_BUCKET_SECS)
+}
+
+// random row of the sharded global counter
+fn global_shard() -> i32 {
+    rand::thread_rng().gen_range(0..GLOBAL_STAT_SHARDS)
+}
+
+//ScyllaDb RowData
+#[derive(FromRow, Serialize, Clone)]
+pub struct UserDetails {
+    pub address: String,
+    pub x: i32,     //u32 aan sherikkum , but CQL derive does'nt support : )
+    pub y: i32,     // same as above : )
+    pub color: i32, // sherikkum u8
+    pub last_placed: i64,
+    // null for rows written before cooldown scaling
+    pub streak: Option<i32>,
+    // next nonce the user signs placements with, null reads as 0
+    pub nonce: Option<i64>,
+    // ink balance as of ink_updated_at, null is a full budget
+    pub ink: Option<i64>,
+    pub ink_updated_at: Option<i64>,
+}
+
+impl UserDetails {
+    // last placed pixel with the i32 columns checked back into u32
+    // None when the user's last action was an erase (the row holds ERASED_COLOR)
+    pub fn pixel(&self) -> Result<Option<Pixel>, VpError> {
+        if self.color == ERASED_COLOR {
+            return Ok(None);
+        }
+        Pixel::try_from(self).map(Some)
+    }
+}
+
+// a placed pixel in canvas coordinates
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pixel {
+    pub x: u32,
+    pub y: u32,
+    pub color: u32,
+}
+
+impl TryFrom<&UserDetails> for Pixel {
+    type Error = VpError;
+
+    fn try_from(user: &UserDetails) -> Result<Self, Self::Error> {
+        Ok(Pixel {
+            x: u32::try_from(user.x)?,
+            y: u32::try_from(user.y)?,
+            color: u32::try_from(user.color)?,
+        })
+    }
+}
+
+#[derive(IntoUserType, FromUserType, Serialize, Deserialize, Clone)]
+pub struct PixelData {
+    pub address: String,
+    pub color: i32,
+    pub last_placed: i64,
+    // short note of the placement, for inspect tooltips
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+
+    fn test_builder() -> ScyllaBuilder {
+        block_on(ScyllaBuilder::try_init(
+            "127.0.0.1:9042",
+            100,
+            ReplicationConfig::default(),
+            ConnectRetry::default(),
+        ))
+        .unwrap()
+    }
+
+    fn test_config(canvas_dim: u32, shards: u32) -> CanvasConfig {
+        CanvasConfig {
+            canvas_dim,
+            shards,
+            color_mode: ColorMode::default(),
+            reserved_regions: Vec::new(),
+            region_cooldowns: Vec::new(),
+        }
+    }
+
+    fn test_canvas(canvas_dim: u32, shards: u32) -> Canvas {
+        Canvas::new(None, test_config(canvas_dim, shards))
+    }
+
+    const ADMIN: &str = "0xAbCdEf0000000000000000000000000000000001";
+    const PLAYER: &str = "0x1111111111111111111111111111111111111111";
+
+    fn test_rules() -> PlacementRules {
+        let builder = test_builder().with_admin_addresses([ADMIN.to_string()]);
+        PlacementRules {
+            cooldown: 60,
+            cooldown_scaling: None,
+            ink: None,
+            free_placements: 0,
+            admin_addresses: builder.admin_addresses,
+        }
+    }
+
+    // player row of address right after a placement at last_placed
+    fn placed_user(address: &str, last_placed: i64) -> UserDetails {
+        UserDetails {
+            address: address.to_lowercase(),
+            last_placed,
+            nonce: Some(1),
+            ..test_user(0)
+        }
+    }
+
+    fn test_user(color: i32) -> UserDetails {
+        UserDetails {
+            address: String::new(),
+            x: 3,
+            y: 4,
+            color,
+            last_placed: 0,
+            streak: None,
+            nonce: None,
+            ink: None,
+            ink_updated_at: None,
+        }
+    }
+
+    #[test]
+    fn erased_user_pixel_is_none() {
+        assert_eq!(
+            test_user(5).pixel().unwrap(),
+            Some(Pixel {
+                x: 3,
+                y: 4,
+                color: 5
+            })
+        );
+        assert_eq!(test_user(ERASED_COLOR).pixel().unwrap(), None);
+    }
+
+    #[test]
+    fn only_existing_columns_count_as_migrated() {
+        let invalid = |msg: &str| QueryError::DbError(DbError::Invalid, msg.to_string());
+        assert!(already_applied(&invalid(
+            "Invalid column name streak because it conflicts with an existing column"
+        )));
+        assert!(already_applied(&invalid(
+            "Cannot add new field tag to type ks.pixel_data: a field of the same name already exists"
+        )));
+        assert!(!already_applied(&invalid(
+            "line 1:10 no viable alternative at input"
+        )));
+        assert!(!already_applied(&invalid(
+            "Cannot change the type of column nonce"
+        )));
+        assert!(!already_applied(&QueryError::DbError(
+            DbError::SyntaxError,
+            "already exists".to_string()
+        )));
+    }
+
+    #[test]
+    fn migrating_twice_is_a_no_op() {
+        let first = pending_migrations(0).collect::<Vec<_>>();
+        assert_eq!(first.len(), MIGRATIONS.len());
+        assert_eq!(first[0], (1, MIGRATIONS[0]));
+        // the second run starts at the version the first one stored
+        let stored = first.last().map_or(0, |(version, _)| *version);
+        assert_eq!(pending_migrations(stored).count(), 0);
+        let partly = pending_migrations(MIGRATIONS.len() - 1).collect::<Vec<_>>();
+        assert_eq!(
+            partly,
+            [(MIGRATIONS.len(), MIGRATIONS[MIGRATIONS.len() - 1])]
+        );
+        assert_eq!(pending_migrations(MIGRATIONS.len() + 3).count(), 0);
+    }
+
+    #[test]
+    fn consistency_levels_map_to_the_driver() {
+        let builder = test_builder()
+            .with_read_consistency(DbConsistency::parse("one").unwrap())
+            .with_write_consistency(DbConsistency::parse("LOCAL_QUORUM").unwrap());
+        assert!(matches!(
+            builder.read_consistency.map(Consistency::from),
+            Some(Consistency::One)
+        ));
+        assert!(matches!(
+            builder.write_consistency.map(Consistency::from),
+            Some(Consistency::LocalQuorum)
+        ));
+        assert!(matches!(
+            Consistency::from(DbConsistency::parse("local_one").unwrap()),
+            Consistency::LocalOne
+        ));
+        assert!(matches!(
+            Consistency::from(DbConsistency::parse("Quorum").unwrap()),
+            Consistency::Quorum
+        ));
+        assert!(matches!(
+            Consistency::from(DbConsistency::parse("ALL").unwrap()),
+            Consistency::All
+        ));
+        assert!(DbConsistency::parse("TWO").is_none());
+        // unset keeps the session default
+        assert!(test_builder().read_consistency.is_none());
+        let mut read = Query::new("SELECT data FROM place.canvas WHERE canvas_part = ?");
+        let mut write = Query::new("INSERT INTO place.player (address) VALUES (?)");
+        set_level(&mut read, builder.read_consistency);
+        set_level(&mut write, builder.write_consistency);
+        assert_eq!(read.get_consistency(), Some(Consistency::One));
+        assert_eq!(write.get_consistency(), Some(Consistency::LocalQuorum));
+        let mut untouched = Query::new("SELECT data FROM place.canvas WHERE canvas_part = ?");
+        set_level(&mut untouched, None);
+        assert_eq!(untouched.get_consistency(), None);
+    }
+
+    #[test]
+    fn pixel_ttl_only_on_canvas_writes() {
+        let builder = test_builder().with_pixel_ttl(3600);
+        assert!(builder.insert_pixel_cql("").ends_with(" USING TTL 3600"));
+        assert!(builder
+            .insert_pixel_cql(" IF NOT EXISTS")
+            .ends_with(" IF NOT EXISTS USING TTL 3600"));
+        assert!(!builder.insert_player_cql("").contains("TTL"));
+        assert!(!builder.insert_player_cql(" IF NOT EXISTS").contains("TTL"));
+        // 0 keeps pixels forever
+        let builder = test_builder().with_pixel_ttl(0);
+        assert!(!builder.insert_pixel_cql("").contains("TTL"));
+    }
+
+    #[test]
+    fn admins_skip_the_cooldown() {
+        let rules = test_rules();
+        assert!(rules.is_admin(ADMIN));
+        assert!(rules.is_admin(&ADMIN.to_lowercase()));
+        assert!(rules.is_admin(&ADMIN.to_uppercase()));
+        assert!(!rules.is_admin(PLAYER));
+        let now = 1_000;
+        // twice in a row : the second placement is a second after the first
+        for address in [ADMIN, PLAYER] {
+            let first = rules.next_placement(address, None, 60, now, false);
+            assert!(first.is_ok());
+        }
+        let user = placed_user(ADMIN, now);
+        let next = rules.next_placement(ADMIN, Some(&user), 60, now + 1, false);
+        assert_eq!(next.unwrap().cooldown_secs, 0);
+        let user = placed_user(PLAYER, now);
+        let next = rules.next_placement(PLAYER, Some(&user), 60, now + 1, false);
+        assert!(matches!(
+            next,
+            Err(VpError::CooldownActive { remaining_secs: 59 })
+        ));
+    }
+
+    #[test]
+    fn grace_covers_the_free_placements_only() {
+        let rules = PlacementRules {
+            free_placements: 3,
+            ..test_rules()
+        };
+        let user = placed_user(PLAYER, 1000);
+        // placements 1 to 3 back to back, the 4th waits out the cooldown
+        for placed in 0..3 {
+            let in_grace = rules.in_grace(placed);
+            assert!(in_grace);
+            let next = rules
+                .next_placement(PLAYER, Some(&user), rules.cooldown, 1001, in_grace)
+                .unwrap();
+            assert_eq!(next.cooldown_secs, 0);
+        }
+        assert!(!rules.in_grace(3));
+        assert!(matches!(
+            rules.next_placement(PLAYER, Some(&user), rules.cooldown, 1001, false),
+            Err(VpError::CooldownActive { remaining_secs: 59 })
+        ));
+        assert!(!test_rules().in_grace(0));
+    }
+
+    #[test]
+    fn color_quota_counts_the_whole_batch() {
+        let builder = test_builder().with_color_quotas([(3, 5)]);
+        let quota = builder.color_quotas[&3];
+        assert_eq!(quota, 5);
+        assert!(!builder.color_quotas.contains_key(&4));
+        assert!(fits_quota(4, 1, quota));
+        assert!(!fits_quota(5, 1, quota));
+        // a batch fits only as a whole, not up to the quota
+        assert!(fits_quota(2, 3, quota));
+        assert!(!fits_quota(3, 3, quota));
+    }
+
+    #[test]
+    fn reserved_regions_are_inclusive_and_may_overlap() {
+        let canvas = Canvas::new(
+            None,
+            CanvasConfig {
+                reserved_regions: vec![Rect::new(10, 10, 19, 19), Rect::new(15, 15, 24, 24)],
+                ..test_config(100, 2)
+            },
+        );
+        for (x, y) in [(10, 10), (19, 19), (15, 15), (24, 24), (19, 24)] {
+            assert!(canvas.is_reserved(x, y), "({x},{y})");
+        }
+        for (x, y) in [(9, 10), (10, 9), (20, 10), (25, 24), (24, 14)] {
+            assert!(!canvas.is_reserved(x, y), "({x},{y})");
+        }
+        let rules = test_rules();
+        assert!(rules.is_admin(&ADMIN.to_lowercase()));
+        assert!(!rules.is_admin(PLAYER));
+    }
+
+    #[test]
+    fn keyspaces_keep_instances_apart() {
+        let prod = test_builder().with_keyspace("place");
+        let staging = test_builder().with_keyspace("place_staging");
+        assert!(prod
+            .insert_pixel_cql("")
+            .starts_with("INSERT INTO place.canvas "));
+        assert!(staging
+            .insert_player_cql("")
+            .starts_with("INSERT INTO place_staging.player "));
+        assert_ne!(prod.insert_pixel_cql(""), staging.insert_pixel_cql(""));
+        assert!(is_keyspace_name("place_2"));
+        assert!(is_keyspace_name(&"k".repeat(48)));
+        for name in [
+            "",
+            "2place",
+            "_place",
+            "place-staging",
+            "place;DROP",
+            &"k".repeat(49),
+        ] {
+            assert!(!is_keyspace_name(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn region_cooldowns_apply_where_the_next_pixel_goes() {
+        let canvas = Canvas::new(
+            None,
+            CanvasConfig {
+                region_cooldowns: vec![(Rect::new(0, 0, 99, 99), 300), (Rect::new(0, 0, 9, 9), 5)],
+                ..test_config(200, 2)
+            },
+        );
+        let rules = test_rules();
+        let (fast, slow) = (
+            canvas.cooldown_at(1, 1, rules.cooldown),
+            canvas.cooldown_at(50, 50, rules.cooldown),
+        );
+        assert_eq!((fast, slow), (5, 300));
+        assert_eq!(canvas.cooldown_at(150, 150, rules.cooldown), 60);
+        // placed in the fast region at 1000, 6s later only the fast region is open again
+        let user = placed_user(PLAYER, 1000);
+        assert!(rules
+            .next_placement(PLAYER, Some(&user), fast, 1006, false)
+            .is_ok());
+        assert!(matches!(
+            rules.next_placement(PLAYER, Some(&user), slow, 1006, false),
+            Err(VpError::CooldownActive {
+                remaining_secs: 294
+            })
+        ));
+    }
+
+    #[test]
+    fn range_reads_page_as_configured() {
+        assert_eq!(test_builder().page_size, 5000);
+        let configured = test_builder().with_page_size(200).page_size;
+        assert_eq!(configured, 200);
+        assert_eq!(effective_page_size(None, configured), 200);
+        // per call sizes win, nonsense ones still page
+        assert_eq!(effective_page_size(Some(20_000), configured), 20_000);
+        assert_eq!(effective_page_size(Some(0), configured), 1);
+        assert_eq!(effective_page_size(None, -5), 1);
+        let mut scan = Query::new("SELECT x, y, data FROM place.canvas WHERE canvas_part = ?");
+        set_paging(&mut scan, None, configured);
+        assert_eq!(scan.get_page_size(), Some(200));
+        set_paging(&mut scan, Some(20), configured);
+        assert_eq!(scan.get_page_size(), Some(20));
+    }
+
+    #[actix_web::test]
+    async fn stalled_ops_time_out() {
+        let timeout = test_builder()
+            .with_op_timeout(Duration::from_millis(10))
+            .op_timeout;
+        let stalled = futures::future::pending::<Result<(), VpError>>();
+        let res = instrument(false, timeout, "get_pixel", stalled).await;
+        assert!(matches!(res, Err(VpError::OperationTimeout)));
+        let quick = async { Ok(7) };
+        let res = instrument(true, Duration::from_secs(5), "get_pixel", quick).await;
+        assert_eq!(res.unwrap(), 7);
+    }
+
+    #[test]
+    fn daily_quota_resets_at_utc_midnight() {
+        let limit = test_builder().with_daily_quota(2).daily_quota;
+        let midnight = 20_000 * 86400;
+        // rows are keyed by day, the new day reads no row of its own yet
+        let mut counts = HashMap::new();
+        counts.insert(quota_day_at(midnight - 1), 2);
+        let placed_at = |ts| *counts.get(&quota_day_at(ts)).unwrap_or(&0);
+        assert!(!fits_quota(placed_at(midnight - 1), 1, limit));
+        assert!(fits_quota(placed_at(midnight), 1, limit));
+        assert_eq!(quota_day_at(midnight) - quota_day_at(midnight - 1), 1);
+        // the old row expires with its day, a last second write still gets a ttl
+        assert_eq!(secs_to_day_end(quota_day_at(midnight - 1), midnight - 1), 1);
+        assert_eq!(secs_to_day_end(quota_day_at(midnight), midnight), 86400);
+        assert_eq!(quota_day_at(-1), -1);
+    }
+
+    #[test]
+    fn partition_ranges_agree_with_partition_index() {
+        for (canvas_dim, shards) in [(10, 2), (10, 3), (9, 4), (17, 4), (64, 8)] {
+            let canvas = test_canvas(canvas_dim, shards);
+            let infos = canvas.partition_infos();
+            let last = canvas_dim - 1;
+            let split = canvas.split_region(0..=last, 0..=last);
+            for x in 0..canvas_dim {
+                for y in 0..canvas_dim {
+                    let pindex = canvas.partition_index(x, y);
+                    let info = &infos[pindex];
+                    assert!(
+                        info.x_range.contains(&x) && info.y_range.contains(&y),
+                        "({x},{y}) of {canvas_dim}x{canvas_dim}/{shards} not in {}",
+                        info.name
+                    );
+                    let holding = split
+                        .iter()
+                        .filter(|(_, xs, ys)| xs.contains(&x) && ys.contains(&y))
+                        .map(|(part, _, _)| *part)
+                        .collect::<Vec<_>>();
+                    assert_eq!(holding, [pindex], "({x},{y}) of {canvas_dim}/{shards}");
+                }
+            }
+            // every cell is in exactly one partition range
+            let cells: u32 = infos
+                .iter()
+                .map(|i| i.x_range.len() as u32 * i.y_range.len() as u32)
+                .sum();
+            assert_eq!(cells, canvas_dim * canvas_dim);
+        }
+    }
+
+    #[test]
+    fn delta_skips_rows_of_other_canvases() {
+        let row = |id: Option<&str>, x| Ok((id.map(str::to_string), PLAYER.to_string(), x, 0, 1));
+        // newest first : 2 of ours, a bucket's worth of others, then our oldest one
+        let mut rows = vec![row(Some("main"), 2), row(Some("main"), 1)];
+        rows.extend((0..MAX_DELTA_LEN as i32 + 1).map(|x| row(Some("other"), x)));
+        rows.push(row(None, 0));
+        rows.push(row(Some("main"), 0));
+        let delta = block_on(collect_delta("main", futures::stream::iter(rows))).unwrap();
+        assert_eq!(delta.iter().map(|u| u.x).collect::<Vec<_>>(), [0, 1, 2]);
+        assert!(delta.iter().all(|u| u.canvas_id == "main"));
+        // our own rows past the limit still need a resync
+        let ours = (0..MAX_DELTA_LEN as i32 + 1).map(|x| row(Some("main"), x));
+        assert!(matches!(
+            block_on(collect_delta("main", futures::stream::iter(ours))),
+            Err(VpError::DeltaTooOld)
+        ));
+    }
+
+    #[test]
+    fn stamps_pay_the_cooldown_per_pixel() {
+        let rules = test_rules();
+        assert!(rules.check_stamp(PLAYER, 1, 60).is_ok());
+        assert!(matches!(
+            rules.check_stamp(PLAYER, 2, 60),
+            Err(VpError::CooldownActive { remaining_secs: 60 })
+        ));
+        assert!(rules.check_stamp(ADMIN, 400, 60).is_ok());
+        let ink = PlacementRules {
+            ink: Some(InkBudget {
+                max: 10,
+                cost: 1,
+                refill_secs: 1,
+            }),
+            ..test_rules()
+        };
+        assert!(ink.check_stamp(PLAYER, 400, 60).is_ok());
+    }
+
+    #[test]
+    fn shutdown_waits_for_in_flight_writes() {
+        let gate = WriteGate::default();
+        block_on(async {
+            let write = gate.enter().unwrap();
+            let mut close = Box::pin(gate.close());
+            assert!(futures::poll!(close.as_mut()).is_pending());
+            // closing, no new writes get in but the started one still finishes
+            assert!(matches!(gate.enter(), Err(VpError::ShuttingDown)));
+            drop(write);
+            close.await;
+        });
+        assert_eq!(gate.in_flight.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn read_only_refuses_writes_until_turned_off() {
+        let gate = WriteGate::default();
+        // turned on mid write : the running one goes on, new ones are refused
+        let running = gate.enter().unwrap();
+        gate.read_only.store(true, Ordering::SeqCst);
+        assert!(matches!(gate.enter(), Err(VpError::ReadOnlyMode)));
+        // only the running write is counted, a refused one would stall shutdown forever
+        assert_eq!(gate.in_flight.load(Ordering::SeqCst), 1);
+        drop(running);
+        assert_eq!(gate.in_flight.load(Ordering::SeqCst), 0);
+        gate.read_only.store(false, Ordering::SeqCst);
+        assert!(gate.enter().is_ok());
+    }
+
+    #[test]
+    fn huge_colors_are_invalid_not_a_panic() {
+        for mode in [ColorMode::default(), ColorMode::Truecolor] {
+            assert!(matches!(
+                mode.check_color(u32::MAX),
+                Err(VpError::InvalidColor(i32::MAX))
+            ));
+            assert!(mode
+                .check_color(u32::try_from(i32::MAX).unwrap() + 1)
+                .is_err());
+            assert_eq!(mode.check_color(1).unwrap(), 1);
+        }
+        assert_eq!(
+            ColorMode::Truecolor.check_color(0xFF_FFFF).unwrap(),
+            0xFF_FFFF
+        );
+        assert!(ColorMode::Truecolor.check_color(0x100_0000).is_err());
+    }
+
+    #[test]
+    fn bounds_at_the_edge() {
+        let canvas = test_canvas(100, 2);
+        assert!(canvas.check_bounds(99, 99).is_ok());
+        assert!(matches!(
+            canvas.check_bounds(100, 0),
+            Err(VpError::OutOfBounds { x: 100, y: 0 })
+        ));
+        assert!(canvas.check_bounds(0, 100).is_err());
+    }
+
+    #[test]
+    fn region_corners_are_validated() {
+        let canvas = test_canvas(100, 2);
+        assert!(canvas.check_region(0, 0, 99, 99).is_ok());
+        assert!(canvas.check_region(5, 5, 5, 5).is_ok());
+        assert!(matches!(
+            canvas.check_region(0, 0, 100, 99),
+            Err(VpError::OutOfBounds { .. })
+        ));
+        assert!(matches!(
+            canvas.check_region(0, 0, 99, 100),
+            Err(VpError::OutOfBounds { .. })
+        ));
+        assert!(matches!(
+            canvas.check_region(10, 0, 9, 5),
+            Err(VpError::InvalidRegion { .. })
+        ));
+        assert!(matches!(
+            canvas.check_region(0, 10, 5, 9),
+            Err(VpError::InvalidRegion { .. })
+        ));
+    }
 }
@@ -0,0 +1,38 @@
+use futures::future::BoxFuture;
+use redis::aio::ConnectionManager;
+
+use crate::models::err_models::VpError;
+use crate::models::scylla_models::PixelUpdate;
+
+// placement events for analytics / other services, after the db write succeeded
+// boxed futures like RateLimiter, so kafka / nats publishers can be plugged in : )
+pub trait EventSink: Send + Sync {
+    fn publish<'a>(&'a self, ev: &'a PixelUpdate) -> BoxFuture<'a, Result<(), VpError>>;
+}
+
+// json events on a redis pub/sub channel
+pub struct RedisEventSink {
+    conn: ConnectionManager,
+    channel: String,
+}
+impl RedisEventSink {
+    pub async fn new(redis: redis::Client, channel: &str) -> Result<Self, VpError> {
+        Ok(Self {
+            conn: redis.get_tokio_connection_manager().await?,
+            channel: channel.to_string(),
+        })
+    }
+}
+impl EventSink for RedisEventSink {
+    fn publish<'a>(&'a self, ev: &'a PixelUpdate) -> BoxFuture<'a, Result<(), VpError>> {
+        Box::pin(async move {
+            let Ok(payload) = serde_json::to_string(ev) else {
+                return Ok(());
+            };
+            redis::Cmd::publish(&self.channel, payload)
+                .query_async::<_, ()>(&mut self.conn.clone())
+                .await?;
+            Ok(())
+        })
+    }
+}
@@ -1 +1,5 @@
+pub mod event_services;
 pub mod p_services;
+pub mod rate_services;
+pub mod render_services;
+pub mod sig_services;
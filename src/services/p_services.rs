@@ -1,9 +1,14 @@
-use chrono::Utc;
+use actix::Addr;
+use futures::TryStreamExt;
 use redis::Client;
+use std::time::Duration;
+
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{timeout_at, Instant};
 
 use crate::models::err_models::VpError;
-use crate::models::p_models::AppState;
-use crate::models::scylla_models::ScyllaManager;
+use crate::models::p_models::{AppState, PixelBatch, PixelLoc, UpdatePixel, VpSrv};
+use crate::models::scylla_models::{PixelUpdate, ScyllaManager};
 
 pub async fn init_place(app_state: &AppState<'_>, redis: &Client) -> Result<(), VpError> {
     let mut conn = redis.get_tokio_connection_manager().await?;
@@ -11,22 +16,116 @@ pub async fn init_place(app_state: &AppState<'_>, redis: &Client) -> Result<(),
         .canvas_dim
         .try_into()
         .map_err(|_| VpError::InitCanvasErr)?;
-    let canvas_size: usize = (dim * dim + 1) / 2;
+    let canvas_size: usize = (dim * dim).div_ceil(2);
     log::debug!("Canvas Bitfield size {}", canvas_size);
     redis::Cmd::set(app_state.canvas_id.as_bytes(), vec![0u8; canvas_size])
-        .query_async(&mut conn)
+        .query_async::<_, ()>(&mut conn)
         .await?;
     Ok(())
 }
-pub async fn diff_last_placed(
-    address: &String,
-    cooldown: usize,
+
+// rebuilds the redis canvas from the pixels stored in scylla
+// the bitfield is assembled in memory and swapped in with a single SET,
+// so cells removed from scylla are cleared too : )
+// placements landing while the canvas is scanned may be missed until placed again
+pub async fn sync_place(
+    app_state: &AppState<'_>,
+    redis: &Client,
     scylla: &ScyllaManager,
-) -> Result<i64, VpError> {
-    let res = scylla.get_user(address).await;
-    match res {
-        Ok(user) => Ok(Utc::now().timestamp() - user.last_placed),
-        Err(VpError::InvalidUser) => Ok(i64::try_from(cooldown)?),
-        Err(e) => Err(e),
+) -> Result<(), VpError> {
+    let mut conn = redis.get_tokio_connection_manager().await?;
+    let dim: usize = app_state
+        .canvas_dim
+        .try_into()
+        .map_err(|_| VpError::InitCanvasErr)?;
+    let mut canvas = vec![0u8; (dim * dim).div_ceil(2)];
+    let mut pixels = Box::pin(scylla.stream_canvas(&app_state.canvas_id, None)?);
+    while let Some((x, y, pixel)) = pixels.try_next().await? {
+        let offset = usize::try_from(x * app_state.canvas_dim + y)?;
+        let (Some(cell), Ok(color)) = (canvas.get_mut(offset / 2), u8::try_from(pixel.color))
+        else {
+            continue;
+        };
+        // u4 bitfield, even offsets are the high nibble
+        if offset % 2 == 0 {
+            *cell = (*cell & 0x0F) | (color << 4);
+        } else {
+            *cell = (*cell & 0xF0) | (color & 0x0F);
+        }
+    }
+    redis::Cmd::set(app_state.canvas_id.as_bytes(), canvas)
+        .query_async::<_, ()>(&mut conn)
+        .await?;
+    Ok(())
+}
+
+// forwards scylla pixel updates of canvas_id to the websocket clients
+// with coalesce, updates arriving within the window after one go out as a single PixelBatch
+// (a window holding one update sends it as is). None sends every update right away
+pub async fn forward_updates(
+    mut updates: broadcast::Receiver<PixelUpdate>,
+    canvas_id: String,
+    vp_srv: Addr<VpSrv<'static>>,
+    coalesce: Option<Duration>,
+) {
+    while let Some(first) = next_update(&mut updates, &canvas_id).await {
+        let Some(window) = coalesce else {
+            vp_srv.do_send(client_update(first));
+            continue;
+        };
+        let deadline = Instant::now() + window;
+        let mut pixels = vec![client_update(first)];
+        let mut closed = false;
+        while let Ok(next) = timeout_at(deadline, next_update(&mut updates, &canvas_id)).await {
+            match next {
+                Some(update) => pixels.push(client_update(update)),
+                None => {
+                    closed = true;
+                    break;
+                }
+            }
+        }
+        match pixels.len() {
+            1 => vp_srv.do_send(pixels.remove(0)),
+            _ => vp_srv.do_send(PixelBatch { pixels }),
+        }
+        if closed {
+            break;
+        }
+    }
+}
+
+// next update of canvas_id, None once the channel is closed
+async fn next_update(
+    updates: &mut broadcast::Receiver<PixelUpdate>,
+    canvas_id: &str,
+) -> Option<PixelUpdate> {
+    loop {
+        match updates.recv().await {
+            Ok(update) if update.canvas_id != canvas_id => {}
+            Ok(update) => return Some(update),
+            Err(RecvError::Lagged(skipped)) => {
+                log::warn!("Websocket feed lagged, {} pixel updates dropped", skipped)
+            }
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+// address not send to client : )
+fn client_update(update: PixelUpdate) -> UpdatePixel {
+    UpdatePixel {
+        address: None,
+        loc: PixelLoc {
+            x: update.x,
+            y: update.y,
+        },
+        color: update.color,
+        signature: None,
+        nonce: None,
+        idempotency_key: None,
+        placed_at: None,
+        team: None,
+        tag: None,
     }
 }
@@ -0,0 +1,87 @@
+use std::time::Instant;
+
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+
+use crate::models::err_models::VpError;
+
+// limits placements per client (ip), on top of the per wallet cooldown
+// boxed futures so a shared (redis) limiter can be plugged in for multi instance setups : )
+pub trait RateLimiter: Send + Sync {
+    // takes `tokens` from the bucket of key, RateLimited when it runs dry
+    fn acquire<'a>(&'a self, key: &'a str, tokens: u32) -> BoxFuture<'a, Result<(), VpError>>;
+    // would acquire pass ? nothing is taken
+    fn peek<'a>(&'a self, key: &'a str, tokens: u32) -> BoxFuture<'a, Result<(), VpError>>;
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// token bucket per key, in process memory : single instance only
+pub struct MemoryRateLimiter {
+    buckets: DashMap<String, Bucket>,
+    // bucket size, max burst of placements
+    capacity: f64,
+    // tokens added back per second
+    refill_per_sec: f64,
+}
+impl MemoryRateLimiter {
+    pub fn new(capacity: u32, refill_per_sec: f64) -> Self {
+        Self {
+            buckets: DashMap::new(),
+            capacity: f64::from(capacity.max(1)),
+            refill_per_sec: refill_per_sec.max(f64::MIN_POSITIVE),
+        }
+    }
+    fn take(&self, key: &str, tokens: u32) -> Result<(), VpError> {
+        let now = Instant::now();
+        let mut bucket = self.buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.updated = now;
+        self.check(bucket.tokens, tokens)?;
+        bucket.tokens -= f64::from(tokens);
+        Ok(())
+    }
+    fn check(&self, available: f64, tokens: u32) -> Result<(), VpError> {
+        let tokens = f64::from(tokens);
+        if available >= tokens {
+            return Ok(());
+        }
+        // a request bigger than the bucket can never pass, it waits for a full bucket
+        let missing = tokens.min(self.capacity) - available;
+        Err(VpError::RateLimited {
+            retry_after: (missing / self.refill_per_sec).ceil() as u64,
+        })
+    }
+    fn available(&self, key: &str) -> f64 {
+        self.buckets.get(key).map_or(self.capacity, |bucket| {
+            let elapsed = Instant::now().duration_since(bucket.updated).as_secs_f64();
+            (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity)
+        })
+    }
+    // drops buckets that refilled completely, they behave like new ones
+    // call it periodically or the map grows with every client ever seen
+    pub fn prune(&self) {
+        let now = Instant::now();
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * self.refill_per_sec < self.capacity
+        });
+    }
+}
+impl RateLimiter for MemoryRateLimiter {
+    fn acquire<'a>(&'a self, key: &'a str, tokens: u32) -> BoxFuture<'a, Result<(), VpError>> {
+        Box::pin(futures::future::ready(self.take(key, tokens)))
+    }
+    fn peek<'a>(&'a self, key: &'a str, tokens: u32) -> BoxFuture<'a, Result<(), VpError>> {
+        Box::pin(futures::future::ready(
+            self.check(self.available(key), tokens),
+        ))
+    }
+}
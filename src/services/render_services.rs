@@ -0,0 +1,179 @@
+// helpers for the png export : integer upscaling and a bundled 5x7 bitmap font
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+// blank columns / rows around each glyph and the caption box
+const GLYPH_SPACING: usize = 1;
+const CAPTION_MARGIN: usize = 2;
+// watermarks are cut to this many chars
+pub const MAX_WATERMARK_LEN: usize = 64;
+
+// rows top to bottom, bit 4 is the leftmost column
+const DIGITS: [[u8; GLYPH_HEIGHT]; 10] = [
+    [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+    [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+    [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+    [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+    [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+    [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+    [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+    [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+    [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+];
+const LETTERS: [[u8; GLYPH_HEIGHT]; 26] = [
+    [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+    [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+    [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+    [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+    [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+    [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+    [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+    [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+    [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+    [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+    [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+    [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+    [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+    [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+    [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+    [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+];
+const QUESTION: [u8; GLYPH_HEIGHT] = [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04];
+
+// lowercase is drawn uppercase, anything unknown as '?'
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        c @ '0'..='9' => DIGITS[c as usize - '0' as usize],
+        c @ 'A'..='Z' => LETTERS[c as usize - 'A' as usize],
+        ' ' => [0; GLYPH_HEIGHT],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '@' => [0x0E, 0x11, 0x17, 0x15, 0x17, 0x10, 0x0F],
+        '#' => [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+        _ => QUESTION,
+    }
+}
+
+// every pixel of a dim x dim rgb image becomes a scale x scale block, no smoothing : )
+pub fn upscale(rgb: &[u8], dim: usize, scale: usize) -> Vec<u8> {
+    if scale <= 1 {
+        return rgb.to_vec();
+    }
+    let out_dim = dim * scale;
+    let mut out = Vec::with_capacity(out_dim * out_dim * 3);
+    for row in rgb.chunks_exact(dim * 3) {
+        let wide: Vec<u8> = row
+            .chunks_exact(3)
+            .flat_map(|px| px.repeat(scale))
+            .collect();
+        for _ in 0..scale {
+            out.extend_from_slice(&wide);
+        }
+    }
+    out
+}
+
+// one mip level down of a dim x dim grid of color bytes (x major), ceil(dim / 2) per side
+// each 2x2 block keeps its most common color, ties go to the first cell of the block
+// so edges stay hard like pixel art should : )
+pub fn downsample_majority<T: Copy + PartialEq>(cells: &[T], dim: usize) -> Vec<T> {
+    let half = dim.div_ceil(2);
+    let mut out = Vec::with_capacity(half * half);
+    for x in 0..half {
+        for y in 0..half {
+            let block: Vec<T> = [(0, 0), (0, 1), (1, 0), (1, 1)]
+                .iter()
+                .map(|(dx, dy)| (2 * x + dx, 2 * y + dy))
+                .filter(|(bx, by)| *bx < dim && *by < dim)
+                .map(|(bx, by)| cells[bx * dim + by])
+                .collect();
+            let count = |c: T| block.iter().filter(|b| **b == c).count();
+            let mut majority = block[0];
+            for c in &block[1..] {
+                if count(*c) > count(majority) {
+                    majority = *c;
+                }
+            }
+            out.push(majority);
+        }
+    }
+    out
+}
+
+// truecolor cells are 0xRRGGBB
+pub fn pack_rgb([r, g, b]: [u8; 3]) -> i32 {
+    i32::from_be_bytes([0, r, g, b])
+}
+pub fn unpack_rgb(color: i32) -> [u8; 3] {
+    let [_, r, g, b] = color.to_be_bytes();
+    [r, g, b]
+}
+
+// white caption on a darkened box in the bottom right corner of a width x height rgb image
+// glyphs are drawn text_scale times bigger, whatever doesn't fit on the left is cut
+pub fn stamp_text(rgb: &mut [u8], width: usize, height: usize, text: &str, text_scale: usize) {
+    let text_scale = text_scale.max(1);
+    let chars: Vec<char> = text.chars().take(MAX_WATERMARK_LEN).collect();
+    if chars.is_empty() {
+        return;
+    }
+    let advance = (GLYPH_WIDTH + GLYPH_SPACING) * text_scale;
+    let box_w = chars.len() * advance + GLYPH_SPACING * text_scale;
+    let box_h = (GLYPH_HEIGHT + 2 * GLYPH_SPACING) * text_scale;
+    if box_h + CAPTION_MARGIN > height {
+        return;
+    }
+    // right aligned, so the box may start left of the image
+    let right = width.saturating_sub(CAPTION_MARGIN);
+    let left = right as isize - box_w as isize;
+    let top = height - CAPTION_MARGIN - box_h;
+    let mut paint = |x: isize, y: usize, f: &dyn Fn(u8) -> u8| {
+        let Ok(x) = usize::try_from(x) else {
+            return;
+        };
+        if x < width && y < height {
+            let offset = (y * width + x) * 3;
+            rgb[offset..offset + 3].iter_mut().for_each(|c| *c = f(*c));
+        }
+    };
+    for y in top..top + box_h {
+        for x in left..right as isize {
+            paint(x, y, &|c| c / 2);
+        }
+    }
+    let origin_y = top + GLYPH_SPACING * text_scale;
+    for (i, c) in chars.iter().enumerate() {
+        let origin_x = left + (GLYPH_SPACING * text_scale + i * advance) as isize;
+        for (row, bits) in glyph(*c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (0x10 >> col) == 0 {
+                    continue;
+                }
+                for dy in 0..text_scale {
+                    for dx in 0..text_scale {
+                        let x = origin_x + (col * text_scale + dx) as isize;
+                        paint(x, origin_y + row * text_scale + dy, &|_| 0xFF);
+                    }
+                }
+            }
+        }
+    }
+}
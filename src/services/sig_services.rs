@@ -0,0 +1,95 @@
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use sha3::{Digest, Keccak256};
+
+use crate::models::err_models::VpError;
+use crate::models::p_models::UpdatePixel;
+
+// message the wallet signs for a placement
+// x,y,color and nonce are part of it so a signature can't be replayed for another pixel : )
+// a tag is appended when set, untagged placements sign the same message as before
+pub fn placement_message(req: &UpdatePixel, nonce: u64) -> String {
+    let message = format!(
+        "opbnb-place: place pixel ({},{}) color {} nonce {}",
+        req.loc.x, req.loc.y, req.color, nonce
+    );
+    match &req.tag {
+        Some(tag) => format!("{} tag {}", message, tag),
+        None => message,
+    }
+}
+
+// message the wallet signs to erase its pixel, distinct so a placement signature can't erase
+pub fn erase_message(req: &UpdatePixel, nonce: u64) -> String {
+    format!(
+        "opbnb-place: erase pixel ({},{}) nonce {}",
+        req.loc.x, req.loc.y, nonce
+    )
+}
+
+// EIP-191 personal_sign hash
+fn eip191_hash(message: &str) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.update(format!("\x19Ethereum Signed Message:\n{}", message.len()));
+    hasher.update(message);
+    hasher.finalize().into()
+}
+
+pub fn recover_address(signature: &[u8], message: &str) -> Result<String, VpError> {
+    // 65 bytes r || s || v
+    if signature.len() != 65 {
+        return Err(VpError::SignatureMismatch);
+    }
+    let sig = Signature::from_slice(&signature[..64]).map_err(|_| VpError::SignatureMismatch)?;
+    // wallets send v as 27/28, some as 0/1
+    let v = match signature[64] {
+        v @ 27..=28 => v - 27,
+        v => v,
+    };
+    let recid = RecoveryId::from_byte(v).ok_or(VpError::SignatureMismatch)?;
+    let key = VerifyingKey::recover_from_prehash(&eip191_hash(message), &sig, recid)
+        .map_err(|_| VpError::SignatureMismatch)?;
+    // address -> last 20 bytes of keccak(uncompressed pubkey without 0x04 prefix)
+    let pubkey = key.to_encoded_point(false);
+    let hash = Keccak256::digest(&pubkey.as_bytes()[1..]);
+    Ok(format!("0x{}", hex::encode(&hash[12..])))
+}
+
+// 0x + 40 hex chars, returned lowercase so a wallet is always the same row
+// mixed case input must carry a valid EIP-55 checksum, a typo in it is rejected : )
+pub fn normalize_address(address: &str) -> Result<String, VpError> {
+    let invalid = || VpError::InvalidAddressFormat(address.to_string());
+    let hex = address.strip_prefix("0x").ok_or_else(invalid)?;
+    if hex.len() != 40 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let lower = hex.to_ascii_lowercase();
+    let mixed =
+        hex.bytes().any(|b| b.is_ascii_lowercase()) && hex.bytes().any(|b| b.is_ascii_uppercase());
+    if mixed {
+        // letter i is uppercase iff nibble i of keccak(lowercase hex) is >= 8
+        let hash = Keccak256::digest(lower.as_bytes());
+        let checksummed = lower.bytes().enumerate().all(|(i, b)| {
+            let nibble = (hash[i / 2] >> (4 * (1 - i % 2))) & 0x0F;
+            hex.as_bytes()[i]
+                == if nibble >= 8 {
+                    b.to_ascii_uppercase()
+                } else {
+                    b
+                }
+        });
+        if !checksummed {
+            return Err(invalid());
+        }
+    }
+    Ok(format!("0x{}", lower))
+}
+
+pub fn verify_placement(req: &UpdatePixel, signature: &[u8], message: &str) -> Result<(), VpError> {
+    let address = req.address.as_ref().ok_or(VpError::InvalidUser)?;
+    let signer = recover_address(signature, message)?;
+    if signer.eq_ignore_ascii_case(address) {
+        Ok(())
+    } else {
+        Err(VpError::SignatureMismatch)
+    }
+}